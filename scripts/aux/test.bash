@@ -99,8 +99,19 @@ remove_empty_dirs() {
    done
 }
 
+start_telegram_api() {
+   local -r port_file="$(mktemp)"
+   "${STORM_HOME}/scripts/mock/telegram-api" > "$port_file" &
+   trap "kill $! &>/dev/null || true" EXIT
+
+   while [ ! -s "$port_file" ]; do sleep 0.1; done
+   export TELEGRAM_API_URL="http://127.0.0.1:$(cat "$port_file")"
+   rm "$port_file"
+}
+
 setup_env() {
    export PATH="${STORM_HOME}/scripts/mock:${PATH}"
+   start_telegram_api
 
    local -r txt="$(cat <<EOF
 =
@@ -204,6 +215,7 @@ telegram:
    token: mockToken
    chat_id: mockChatId
    db_path: ${DB}
+   api_url: ${TELEGRAM_API_URL}
 EOF
    )"
 
@@ -1,6 +1,6 @@
 use crate::archive;
 use crate::fs;
-use crate::rclone;
+use crate::provider;
 use anyhow::{self, Result};
 use std::path::{Path, PathBuf};
 use walkdir::WalkDir;
@@ -15,7 +15,7 @@ pub fn download(list: &Path, to: &Path, start: usize, files: usize) -> Result<()
         .collect();
     dbg!(&allowlist);
 
-    rclone::pull_many("alumni", &allowlist[..], to)?;
+    provider::backend("alumni")?.get_many(&allowlist[..], to)?;
 
     let mut camera = to.to_owned();
     camera.push("Pictures");
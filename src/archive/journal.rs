@@ -0,0 +1,178 @@
+use crate::fs::{self, IPathBuf};
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub(super) enum Phase {
+    Selected(Vec<PathBuf>),
+    Downloaded,
+    Unzipped,
+    Zipping(PathBuf),
+    Zipped(Zipped),
+    Cataloged,
+    Done,
+}
+
+/// What the catalog says about a zip, kept so that it can be written after
+/// the zip left the buffer
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Zipped {
+    pub hash: String,
+    pub size: u64,
+    /// 1 when it wasn't split
+    pub volumes: usize,
+}
+
+#[derive(Debug, Default)]
+pub(super) struct Pending {
+    pub zip_id: usize,
+    pub files: Vec<PathBuf>,
+    pub zip_path: Option<PathBuf>,
+    pub zipped: Option<Zipped>,
+    pub cataloged: bool,
+}
+
+impl Zipped {
+    fn from_arg(arg: &str) -> Result<Self> {
+        let mut parts = arg.split(';');
+        let hash = parts.next().context("no hash")?.to_owned();
+        let size = parts.next().context("no size")?.parse()?;
+        let volumes = parts.next().context("no volumes")?.parse()?;
+        Ok(Self { hash, size, volumes })
+    }
+}
+
+pub(super) struct Journal {
+    path: PathBuf,
+}
+
+impl Phase {
+    fn lines(&self, zip_id: usize) -> Vec<String> {
+        let line = |name: &str, arg: &str| format!("{};{};{}", zip_id, name, arg);
+
+        match self {
+            Phase::Selected(files) => files.iter().map(|f| line("selected", &f.to_string())).collect(),
+            Phase::Downloaded => vec![line("downloaded", "")],
+            Phase::Unzipped => vec![line("unzipped", "")],
+            Phase::Zipping(zip_path) => vec![line("zipping", &zip_path.to_string())],
+            Phase::Zipped(z) => vec![line("zipped", &format!("{};{};{}", z.hash, z.size, z.volumes))],
+            Phase::Cataloged => vec![line("cataloged", "")],
+            Phase::Done => vec![line("done", "")],
+        }
+    }
+}
+
+impl Journal {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path }
+    }
+
+    pub fn record(&self, zip_id: usize, phase: &Phase) -> Result<()> {
+        let mut txt = phase.lines(zip_id).join("\n");
+        txt.push('\n');
+        fs::append(&self.path, &txt)
+    }
+
+    /// The last zip id and whether it still has unfinished steps
+    pub fn pending(&self) -> Result<Option<(usize, Option<Pending>)>> {
+        if !self.path.exists() {
+            return Ok(None);
+        }
+
+        let mut last: Option<(usize, Option<Pending>)> = None;
+
+        for line in fs::read_lines(&self.path)? {
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let mut parts = line.splitn(3, ';');
+            let zip_id: usize = parts.next().context("no zip id")?.parse()?;
+            let name = parts.next().context("no phase")?;
+            let arg = parts.next().unwrap_or_default();
+
+            let is_new = last.as_ref().map(|(id, _)| *id != zip_id).unwrap_or(true);
+            if is_new {
+                let pending = Pending {
+                    zip_id,
+                    ..Default::default()
+                };
+                last = Some((zip_id, Some(pending)));
+            }
+
+            let (_, entry) = last.as_mut().context("no zip id")?;
+            let pending = match entry {
+                Some(p) => p,
+                None => return Err(anyhow!("phase {} after done for zip {}", name, zip_id)),
+            };
+
+            match name {
+                "selected" => pending.files.push(PathBuf::from(arg)),
+                "downloaded" | "unzipped" => {}
+                "zipping" => pending.zip_path = Some(PathBuf::from(arg)),
+                // zipped again if the line is from an older version
+                "zipped" => pending.zipped = Zipped::from_arg(arg).ok(),
+                "cataloged" => pending.cataloged = true,
+                "done" => *entry = None,
+                _ => return Err(anyhow!("invalid journal phase: {}", name)),
+            }
+        }
+
+        Ok(last)
+    }
+
+    pub fn clear(&self) -> Result<()> {
+        fs::remove_file(&self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pending() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let journal = Journal::new(dir.path().join("journal.txt"));
+        assert!(journal.pending()?.is_none());
+
+        let files = vec![PathBuf::from("a/b.jpg"), PathBuf::from("a/c;d.jpg")];
+        journal.record(0, &Phase::Selected(files.clone()))?;
+        journal.record(0, &Phase::Downloaded)?;
+        journal.record(0, &Phase::Unzipped)?;
+        journal.record(0, &Phase::Zipping(PathBuf::from("ByTimestamp/x.7z")))?;
+        journal.record(
+            0,
+            &Phase::Zipped(Zipped {
+                hash: "abc".into(),
+                size: 10,
+                volumes: 1,
+            }),
+        )?;
+        journal.record(0, &Phase::Cataloged)?;
+        journal.record(0, &Phase::Done)?;
+        assert!(matches!(journal.pending()?, Some((0, None))));
+
+        journal.record(1, &Phase::Selected(files.clone()))?;
+        journal.record(1, &Phase::Zipping(PathBuf::from("ByTimestamp/y.7z")))?;
+        let zipped = Zipped {
+            hash: "def".into(),
+            size: 2048,
+            volumes: 3,
+        };
+        journal.record(1, &Phase::Zipped(zipped.clone()))?;
+
+        let (zip_id, pending) = journal.pending()?.context("no entry")?;
+        let pending = pending.context("not pending")?;
+        assert_eq!(zip_id, 1);
+        assert_eq!(pending.files, files);
+        assert_eq!(pending.zip_path, Some(PathBuf::from("ByTimestamp/y.7z")));
+        assert_eq!(pending.zipped, Some(zipped));
+        assert!(!pending.cataloged);
+
+        journal.clear()?;
+        assert!(journal.pending()?.is_none());
+
+        Ok(())
+    }
+}
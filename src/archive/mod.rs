@@ -1,3 +1,8 @@
+mod journal;
+mod pack;
+mod rebuild;
+mod restore;
+mod verify;
 mod work;
 
 use crate::config;
@@ -5,20 +10,26 @@ use crate::db;
 use crate::db::Db;
 use crate::fs::{self, IPathBuf};
 use crate::log;
+use crate::plan;
 use crate::provider;
-use crate::rclone;
 use crate::smalldate::SmallDate;
 use crate::zip;
 use anyhow::{Context, Result};
+use journal::{Journal, Pending, Phase, Zipped};
+use pack::{Candidate, Grouping};
 use regex::Regex;
-use std::io::BufReader;
-use std::io::Read;
 use std::path::Path;
 use std::path::PathBuf;
 use walkdir::WalkDir;
 use work::{should_process, SkipReason};
 
-fn get_db_folder() -> Result<&'static PathBuf> {
+pub use rebuild::rebuild;
+pub use restore::restore;
+pub use verify::verify;
+
+const ZIP_FOLDER: &str = "ByTimestamp";
+
+pub fn get_db_folder() -> Result<&'static PathBuf> {
     config::get()
         .yaml
         .archive
@@ -36,11 +47,17 @@ fn get_tmp_buffer() -> Result<&'static PathBuf> {
         .context("empty archive.tmp_buffer")
 }
 
-fn files_to_zip(ls_lines: &mut dyn Iterator<Item = String>) -> Result<Vec<PathBuf>> {
-    let max_zip_kb = config::get().yaml.archive.max_zip_kb;
-
-    let db_folder = get_db_folder()?;
+fn grouping() -> Result<Option<Grouping>> {
+    match &config::get().yaml.archive.group_by {
+        Some(g) => Ok(Some(Grouping::from_str(g)?)),
+        None => Ok(None),
+    }
+}
 
+/// Backends without md5 support yield no hashes, in which case files are
+/// deduplicated by path only. Selected files are added to `hashes`, so
+/// copies of the same content are only archived once
+fn candidates(entries: Vec<provider::Entry>, hashes: &mut db::Hashes) -> Vec<Candidate> {
     let denylist = config::get()
         .yaml
         .archive
@@ -49,46 +66,91 @@ fn files_to_zip(ls_lines: &mut dyn Iterator<Item = String>) -> Result<Vec<PathBu
         .map(|x| Regex::new(x).expect("invalid regex"))
         .collect::<Vec<_>>();
 
-    let zip_kb_threshold = (0.9 * max_zip_kb as f32) as u32;
-    let mut zip_kb = 0;
-
-    let mut files = vec![];
+    let mut candidates = vec![];
+    let mut volumes = vec![];
 
-    let hashes = db::all_hashes(db_folder);
-
-    for line in ls_lines {
-        let (bytes_str, path_str) = line.trim().split_once(' ').context("unable to split")?;
-        let kb = (bytes_str.trim().parse::<u64>()? / 1024) as u32;
-        let path = PathBuf::from(path_str.trim());
-        let reason = should_process(&path, kb, &denylist, zip_kb, &hashes);
+    for entry in entries.iter().filter(|e| !e.is_dir) {
+        let kb = (entry.size.max(0) / 1024) as u32;
+        // encrypted sources are extracted before being zipped, so the md5 of the
+        // object isn't the md5 of the content that ends up in the catalog
+        let is_volume = zip::volume_of(&entry.path).is_some();
+        let is_7z = is_volume || entry.path.extension().map(|e| e == "7z").unwrap_or(false);
+        let md5 = if is_7z { None } else { entry.md5() };
+        let reason = should_process(&entry.path, kb, md5, &denylist, hashes);
+        plan::decision(&entry.path, &reason);
 
         if let SkipReason::NoSkip = reason {
-            zip_kb += kb;
-            log::debug(&format!(
-                "File included: {}, cumm_mb: {}",
-                &path.to_string(),
-                zip_kb / 1024
-            ));
-            files.push(path);
+            if is_volume {
+                volumes.push(entry.path.clone());
+            } else {
+                db::insert_hash(hashes, &entry.path, md5);
+            }
+            candidates.push(Candidate {
+                path: entry.path.clone(),
+                kb,
+                date: entry.modified().and_then(|m| SmallDate::from_naive(m).ok()),
+            });
         } else {
-            log::warn(&format!("File skipped: {:?}, {}", &reason, &path.to_string()));
+            log::warn(&format!(
+                "File skipped: {:?}, {}",
+                &reason,
+                &entry.path.to_string()
+            ));
         }
+    }
 
-        if zip_kb >= zip_kb_threshold {
-            break;
-        }
+    // volumes share the path of their archive, so they are only recorded once
+    // all of them were selected
+    for path in volumes {
+        db::insert_hash(hashes, &path, None);
     }
 
-    Ok(files)
+    candidates
 }
 
-fn download_files(files: Vec<PathBuf>) -> Result<()> {
+/// Groups every file of the source that still has to be archived into zips
+fn plan_archives() -> Result<Vec<Vec<PathBuf>>> {
+    let provider_id = &config::get().yaml.archive.source_provider;
+    let max_zip_kb = config::get().yaml.archive.max_zip_kb;
+
+    let entries = provider::backend(provider_id)?.list(Path::new("/"))?;
+    let mut hashes = db::all_hashes(get_db_folder()?)?;
+    let candidates = candidates(entries, &mut hashes);
+    let zips = pack::plan(candidates, max_zip_kb, grouping()?);
+
+    for (i, files) in zips.iter().enumerate() {
+        log::debug(&format!("Zip {} planned with {} files", i, files.len()));
+    }
+
+    Ok(zips)
+}
+
+fn download_files(files: &[PathBuf]) -> Result<()> {
     dbg!("download_files");
 
     let provider_id = &config::get().yaml.archive.source_provider;
     let to = get_tmp_buffer()?;
 
-    rclone::pull_many(provider_id, &files, to)?;
+    provider::backend(provider_id)?.get_many(files, to)?;
+
+    Ok(())
+}
+
+/// Volumes are joined back into the archive they were split from
+fn join_volumes(folder: &Path) -> Result<()> {
+    let archives = WalkDir::new(folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter_map(|e| zip::first_volume_archive(e.path()))
+        .collect::<Vec<_>>();
+
+    for archive in archives {
+        let volumes = zip::volumes(&archive);
+        zip::join(&volumes, &archive)?;
+        for volume in &volumes {
+            fs::remove_file(volume)?;
+        }
+    }
 
     Ok(())
 }
@@ -98,6 +160,8 @@ pub fn unzip_files(folder: &Path) -> Result<()> {
 
     let password = config::get().crypto_password()?;
 
+    join_volumes(folder)?;
+
     for entry in WalkDir::new(folder).into_iter().filter_map(|e| e.ok()) {
         let path = entry.path();
         let is_file = entry.metadata().map(|m| m.is_file()).unwrap_or(false);
@@ -119,33 +183,83 @@ pub fn unzip_files(folder: &Path) -> Result<()> {
     Ok(())
 }
 
-fn create_zip() -> Result<(PathBuf, String)> {
-    dbg!("create_zip");
-
-    let buffer = get_tmp_buffer()?;
-    let provider_id = &config::get().yaml.archive.provider;
-
+fn new_zip_path() -> PathBuf {
     let now = chrono::Local::now();
     let timestamp = now.format("%Y-%m-%dT%H-%M-%S");
     let filename = format!("{}.7z", timestamp);
 
-    let relative_to = PathBuf::from("ByTimestamp").join(&filename);
-    let to = provider::get(provider_id)?.buffer.join(&relative_to);
+    PathBuf::from(ZIP_FOLDER).join(&filename)
+}
+
+fn zip_buffer_path(zip_path: &Path) -> Result<PathBuf> {
+    let provider_id = &config::get().yaml.archive.provider;
+    Ok(provider::get(provider_id)?.buffer.join(zip_path))
+}
+
+fn create_zip(zip_path: &Path) -> Result<Zipped> {
+    dbg!("create_zip");
+
+    let buffer = get_tmp_buffer()?;
+    let to = zip_buffer_path(zip_path)?;
 
     let password = config::get().crypto_password()?;
 
-    zip::create(password, buffer, &to)?;
+    let scheme = config::get().password_scheme()?;
+
+    zip::create(scheme, password, buffer, &to)?;
 
-    let file = fs::open(&to)?;
-    let mut reader = BufReader::new(file);
-    let mut buffer = Vec::new();
-    reader.read_to_end(&mut buffer)?;
-    let hash = format!("{:x}", md5::compute(buffer));
+    let hash = fs::md5(&to)?;
+    let size = std::fs::metadata(&to)?.len();
+    let volumes = zip::split_for(&config::get().yaml.archive.provider, &to)?.len();
 
-    Ok((relative_to, hash))
+    Ok(Zipped { hash, size, volumes })
 }
 
-fn save_db(zip_path: &Path, hash: String) -> Result<()> {
+/// Paths of the objects an archive was uploaded as, `volumes` being 0 when
+/// it wasn't split
+fn remote_parts(zip_path: &Path, volumes: usize) -> Vec<PathBuf> {
+    if volumes == 0 {
+        vec![zip_path.into()]
+    } else {
+        (1..=volumes).map(|i| zip::volume_path(zip_path, i)).collect()
+    }
+}
+
+/// Downloads an archive to `local`, joining its volumes if it was split
+fn pull_archive(zip_path: &Path, volumes: usize, local: &Path) -> Result<()> {
+    let provider_id = &config::get().yaml.archive.provider;
+    let backend = provider::backend(provider_id)?;
+    let root = provider::remote_path(provider_id, None)?;
+
+    if volumes == 0 {
+        return backend.get(&root.join(zip_path), local);
+    }
+
+    let mut locals = vec![];
+    for (i, part) in remote_parts(zip_path, volumes).iter().enumerate() {
+        let local_part = zip::volume_path(local, i + 1);
+        backend.get(&root.join(part), &local_part)?;
+        locals.push(local_part);
+    }
+
+    let result = zip::join(&locals, local);
+    for local_part in &locals {
+        fs::remove_file(local_part)?;
+    }
+    result
+}
+
+fn catalog_path_in(db_folder: &Path, zip_path: &Path) -> PathBuf {
+    let mut p = db_folder.join(zip_path);
+    p.set_extension("storm.txt");
+    p
+}
+
+pub fn catalog_path(zip_path: &Path) -> Result<PathBuf> {
+    Ok(catalog_path_in(get_db_folder()?, zip_path))
+}
+
+fn save_db(zip_path: &Path, zipped: &Zipped) -> Result<()> {
     dbg!("save_db");
 
     let mut db = Db::new();
@@ -165,17 +279,20 @@ fn save_db(zip_path: &Path, hash: String) -> Result<()> {
 
     let azure_path = zip_path.to_string();
     db.add_tag("azure_path".into(), azure_path);
-    db.add_tag("hash".into(), hash);
+    db.add_tag("hash".into(), zipped.hash.clone());
+    db.add_tag(db::SIZE_TAG.into(), zipped.size.to_string());
+    if zipped.volumes > 1 {
+        db.add_tag(db::VOLUMES_TAG.into(), zipped.volumes.to_string());
+    }
     db.add_tag("date".into(), SmallDate::now()?.to_string());
+    db.add_tag(
+        db::PASSWORD_SCHEME_TAG.into(),
+        config::get().password_scheme()?.to_string(),
+    );
 
-    let to = {
-        let mut p = get_db_folder()?.clone();
-        p.push(zip_path);
-        p.set_extension("storm.txt");
-        p
-    };
+    let to = catalog_path(zip_path)?;
 
-    fs::write(&to, &db.to_string())?;
+    fs::write(&to, db.to_string())?;
 
     Ok(())
 }
@@ -191,34 +308,111 @@ fn cleanup() -> Result<()> {
     Ok(())
 }
 
-pub fn create_zips() -> Result<()> {
-    let provider_id = &config::get().yaml.archive.source_provider;
-    let ls_res = rclone::ls(provider_id, None)?.res.context("no source output")?;
-    let ls_lines = ls_res.stdout.lines().map(|x| x.to_string());
+fn has_buffered_files() -> Result<bool> {
+    let buffer = get_tmp_buffer()?;
+    let found = WalkDir::new(buffer)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .any(|e| e.metadata().map(|m| m.is_file()).unwrap_or(false));
+    Ok(found)
+}
 
-    let mut zip_id = 0;
-    loop {
-        let mut lines = ls_lines.clone();
-        let files = files_to_zip(&mut lines)?;
-        dbg!((zip_id, &files));
+fn fill_buffer(journal: &Journal, zip_id: usize, files: &[PathBuf]) -> Result<()> {
+    cleanup()?;
+    download_files(files)?;
+    journal.record(zip_id, &Phase::Downloaded)?;
 
-        if files.is_empty() {
-            break;
-        }
+    let tmp_buffer = get_tmp_buffer()?;
+    unzip_files(tmp_buffer)?;
+    remove_unwanted_files()?;
+    journal.record(zip_id, &Phase::Unzipped)
+}
 
-        cleanup()?;
-        download_files(files)?;
+fn catalog_zip(journal: &Journal, zip_id: usize, zip_path: &Path, zipped: &Zipped) -> Result<()> {
+    remove_unwanted_files()?;
+    save_db(zip_path, zipped)?;
+    journal.record(zip_id, &Phase::Cataloged)?;
+
+    cleanup()?;
+    journal.record(zip_id, &Phase::Done)
+}
+
+fn build_zip(journal: &Journal, zip_id: usize, files: &[PathBuf]) -> Result<()> {
+    fill_buffer(journal, zip_id, files)?;
+
+    let zip_path = new_zip_path();
+    journal.record(zip_id, &Phase::Zipping(zip_path.clone()))?;
+    let zipped = create_zip(&zip_path)?;
+    journal.record(zip_id, &Phase::Zipped(zipped.clone()))?;
 
-        let tmp_buffer = get_tmp_buffer()?;
-        unzip_files(tmp_buffer)?;
+    catalog_zip(journal, zip_id, &zip_path, &zipped)
+}
+
+fn resume(journal: &Journal, pending: Pending) -> Result<()> {
+    let zip_id = pending.zip_id;
+    log::warn(&format!("Resuming zip {}: {:?}", zip_id, &pending));
 
-        remove_unwanted_files()?;
-        let (zip_path, hash) = create_zip()?;
-        remove_unwanted_files()?;
-        save_db(&zip_path, hash)?;
+    if pending.cataloged {
         cleanup()?;
-        zip_id += 1;
+        return journal.record(zip_id, &Phase::Done);
+    }
+
+    match (pending.zip_path, pending.zipped) {
+        (Some(zip_path), Some(zipped)) => {
+            if !has_buffered_files()? {
+                fill_buffer(journal, zip_id, &pending.files)?;
+            }
+            catalog_zip(journal, zip_id, &zip_path, &zipped)
+        }
+        (zip_path, _) => {
+            if let Some(p) = zip_path {
+                fs::remove_file(&zip_buffer_path(&p)?)?;
+                for volume in zip::volumes(&zip_buffer_path(&p)?) {
+                    fs::remove_file(&volume)?;
+                }
+            }
+            build_zip(journal, zip_id, &pending.files)
+        }
+    }
+}
+
+fn plan_zips() -> Result<()> {
+    for files in plan_archives()? {
+        download_files(&files)?;
+        let password = config::get().crypto_password()?;
+        let scheme = config::get().password_scheme()?;
+        zip::create(
+            scheme,
+            password,
+            get_tmp_buffer()?,
+            &zip_buffer_path(&new_zip_path())?,
+        )?;
     }
 
     Ok(())
 }
+
+pub fn create_zips() -> Result<()> {
+    if plan::is_dry_run() {
+        return plan_zips();
+    }
+
+    let journal = Journal::new(get_db_folder()?.join("create_zips.journal"));
+
+    let mut zip_id = 0;
+    if let Some((last_id, pending)) = journal.pending()? {
+        if let Some(p) = pending {
+            resume(&journal, p)?;
+        }
+        zip_id = last_id + 1;
+    }
+
+    for files in plan_archives()? {
+        dbg!((zip_id, &files));
+        journal.record(zip_id, &Phase::Selected(files.clone()))?;
+        build_zip(&journal, zip_id, &files)?;
+        zip_id += 1;
+    }
+
+    journal.clear()
+}
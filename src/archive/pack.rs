@@ -0,0 +1,235 @@
+use crate::fs::IPathBuf;
+use crate::smalldate::SmallDate;
+use crate::zip;
+use anyhow::Result;
+use std::cmp::Reverse;
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+/// A file that passed selection and still has to be archived
+#[derive(Debug)]
+pub(super) struct Candidate {
+    pub path: PathBuf,
+    pub kb: u32,
+    pub date: Option<SmallDate>,
+}
+
+/// Files with the same key are kept in the same archive whenever they fit
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Grouping {
+    Directory,
+    Month,
+}
+
+impl Grouping {
+    pub fn from_str(txt: &str) -> Result<Self> {
+        match txt.to_lowercase().as_str() {
+            "directory" => Ok(Self::Directory),
+            "month" => Ok(Self::Month),
+            _ => Err(anyhow!("invalid grouping {}, expected directory or month", txt)),
+        }
+    }
+
+    fn key(&self, candidate: &Candidate) -> String {
+        match self {
+            Self::Directory => candidate.path.parent().map(|p| p.to_string()).unwrap_or_default(),
+            Self::Month => match candidate.date {
+                Some(d) => {
+                    let yymmdd = d.to_string();
+                    yymmdd[..yymmdd.len() - 2].to_owned()
+                }
+                None => String::new(),
+            },
+        }
+    }
+}
+
+struct Bin {
+    kb: u32,
+    files: Vec<PathBuf>,
+}
+
+/// Files that can't be split across archives, which is either a single file or
+/// every volume of a split archive
+struct Unit {
+    kb: u32,
+    files: Vec<PathBuf>,
+}
+
+fn total_kb(group: &[Unit]) -> u32 {
+    group.iter().map(|u| u.kb).sum()
+}
+
+/// First fit, returning whether the files were placed
+fn place(bins: &mut [Bin], kb: u32, files: &mut Vec<PathBuf>, max_zip_kb: u32) -> bool {
+    match bins.iter_mut().find(|b| b.kb + kb <= max_zip_kb) {
+        Some(bin) => {
+            bin.kb += kb;
+            bin.files.append(files);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Units by grouping key, with the volumes of an archive in the group of its
+/// first volume found
+fn group(candidates: Vec<Candidate>, grouping: Option<Grouping>) -> Vec<Vec<Unit>> {
+    let mut by_key: BTreeMap<String, Vec<Unit>> = BTreeMap::new();
+    let mut volumes: BTreeMap<PathBuf, (String, Unit)> = BTreeMap::new();
+
+    for candidate in candidates {
+        let archive = zip::volume_of(&candidate.path).map(|(archive, _)| archive);
+        let key = match (grouping, &archive) {
+            (Some(g), _) => g.key(&candidate),
+            (None, Some(archive)) => archive.to_string(),
+            (None, None) => candidate.path.to_string(),
+        };
+
+        match archive {
+            Some(archive) => {
+                let (_, unit) = volumes
+                    .entry(archive)
+                    .or_insert((key, Unit { kb: 0, files: vec![] }));
+                unit.kb += candidate.kb;
+                unit.files.push(candidate.path);
+            }
+            None => by_key.entry(key).or_default().push(Unit {
+                kb: candidate.kb,
+                files: vec![candidate.path],
+            }),
+        }
+    }
+
+    for (key, unit) in volumes.into_values() {
+        by_key.entry(key).or_default().push(unit);
+    }
+
+    by_key.into_values().collect()
+}
+
+/// Packs every candidate into archives of at most `max_zip_kb`, using first
+/// fit decreasing over groups. A group too big for a single archive is split
+/// unit by unit, into archives of its own as far as possible
+pub(super) fn plan(
+    candidates: Vec<Candidate>,
+    max_zip_kb: u32,
+    grouping: Option<Grouping>,
+) -> Vec<Vec<PathBuf>> {
+    let mut groups = group(candidates, grouping);
+    groups.sort_by_key(|g| Reverse(total_kb(g)));
+
+    let mut bins: Vec<Bin> = vec![];
+
+    for mut group in groups {
+        let kb = total_kb(&group);
+        if kb <= max_zip_kb {
+            let mut files = group.into_iter().flat_map(|u| u.files).collect();
+            if !place(&mut bins, kb, &mut files, max_zip_kb) {
+                bins.push(Bin { kb, files });
+            }
+            continue;
+        }
+
+        group.sort_by_key(|u| Reverse(u.kb));
+        let first = bins.len();
+        for mut unit in group {
+            if !place(&mut bins[first..], unit.kb, &mut unit.files, max_zip_kb) {
+                bins.push(Bin {
+                    kb: unit.kb,
+                    files: unit.files,
+                });
+            }
+        }
+    }
+
+    bins.into_iter()
+        .map(|b| {
+            let mut files = b.files;
+            files.sort();
+            files
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn candidate(path: &str, kb: u32, date: &str) -> Candidate {
+        Candidate {
+            path: PathBuf::from(path),
+            kb,
+            date: SmallDate::from_str(date).ok(),
+        }
+    }
+
+    fn candidates() -> Vec<Candidate> {
+        vec![
+            candidate("a/1.jpg", 60, "220101"),
+            candidate("b/1.jpg", 30, "220102"),
+            candidate("a/2.jpg", 50, "220301"),
+            candidate("b/2.jpg", 40, "220302"),
+            candidate("c/1.jpg", 20, "220303"),
+        ]
+    }
+
+    fn to_strings(plan: Vec<Vec<PathBuf>>) -> Vec<Vec<String>> {
+        plan.into_iter()
+            .map(|zip| zip.iter().map(|p| p.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_plan() {
+        assert_eq!(
+            to_strings(plan(candidates(), 100, None)),
+            vec![vec!["a/1.jpg", "b/2.jpg"], vec!["a/2.jpg", "b/1.jpg", "c/1.jpg"],]
+        );
+
+        assert_eq!(
+            to_strings(plan(candidates(), 110, Some(Grouping::Directory))),
+            vec![vec!["a/1.jpg", "a/2.jpg"], vec!["b/1.jpg", "b/2.jpg", "c/1.jpg"]]
+        );
+
+        assert_eq!(
+            to_strings(plan(candidates(), 100, Some(Grouping::Month))),
+            vec![
+                vec!["a/2.jpg", "b/2.jpg"],
+                vec!["c/1.jpg"],
+                vec!["a/1.jpg", "b/1.jpg"]
+            ]
+        );
+
+        assert_eq!(
+            to_strings(plan(candidates(), 60, Some(Grouping::Directory))),
+            vec![
+                vec!["a/1.jpg"],
+                vec!["a/2.jpg"],
+                vec!["b/2.jpg", "c/1.jpg"],
+                vec!["b/1.jpg"],
+            ]
+        );
+
+        assert!(Grouping::from_str("year").is_err());
+    }
+
+    #[test]
+    fn test_plan_volumes() {
+        let candidates = vec![
+            candidate("a/x.7z.001", 40, "220101"),
+            candidate("b/1.jpg", 50, "220102"),
+            candidate("a/x.7z.002", 40, "220101"),
+            candidate("a/1.jpg", 30, "220301"),
+            candidate("a/x.7z.003", 10, "220101"),
+        ];
+
+        assert_eq!(
+            to_strings(plan(candidates, 100, None)),
+            vec![
+                vec!["a/x.7z.001", "a/x.7z.002", "a/x.7z.003"],
+                vec!["a/1.jpg", "b/1.jpg"]
+            ]
+        );
+    }
+}
@@ -0,0 +1,111 @@
+use super::{catalog_path_in, pull_archive, ZIP_FOLDER};
+use crate::config;
+use crate::db::{self, Db};
+use crate::fs::{self, IPathBuf};
+use crate::log;
+use crate::provider;
+use crate::smalldate::SmallDate;
+use crate::zip;
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+/// Archives are named after the time they were created
+fn zip_date(zip_path: &Path) -> Result<SmallDate> {
+    let stem = zip_path
+        .file_name()
+        .and_then(|f| f.to_str())
+        .map(|f| f.trim_end_matches(".7z"))
+        .context("invalid filename")?;
+
+    match NaiveDateTime::parse_from_str(stem, "%Y-%m-%dT%H-%M-%S") {
+        Ok(naive) => SmallDate::from_naive(naive),
+        Err(_) => SmallDate::now(),
+    }
+}
+
+fn build_db(zip_path: &Path, volumes: usize, local: &Path) -> Result<Db> {
+    let password = config::get().crypto_password()?;
+    let scheme = zip::detect_scheme(password, local)?;
+    let date = zip_date(zip_path)?;
+
+    let mut db = Db::new();
+
+    for entry in zip::list(scheme, password, local)? {
+        if entry.is_dir {
+            continue;
+        }
+        let kb = (entry.bytes / 1024) as u32;
+        let file_date = match entry.modified {
+            Some(m) => SmallDate::from_naive(m)?,
+            None => date,
+        };
+        db.add(&entry.path, kb, file_date, None)?;
+    }
+
+    db.add_tag("azure_path".into(), zip_path.to_string());
+    db.add_tag("hash".into(), fs::md5(local)?);
+    db.add_tag(db::SIZE_TAG.into(), std::fs::metadata(local)?.len().to_string());
+    db.add_tag("date".into(), date.to_string());
+    db.add_tag(db::PASSWORD_SCHEME_TAG.into(), scheme.to_string());
+    if volumes > 0 {
+        db.add_tag(db::VOLUMES_TAG.into(), volumes.to_string());
+    }
+
+    Ok(db)
+}
+
+fn rebuild_one(zip_path: &Path, volumes: usize, catalog: &Path, tmp: &Path) -> Result<()> {
+    let local = tmp.join(zip_path.file_name().context("no filename")?);
+
+    pull_archive(zip_path, volumes, &local)?;
+    let db = build_db(zip_path, volumes, &local);
+    fs::remove_file(&local)?;
+
+    db::write(db?, catalog)
+}
+
+/// Writes a catalog for every remote archive that has none, or for all of
+/// them when overwriting
+pub fn rebuild(db_folder: &Path, overwrite: bool) -> Result<()> {
+    let provider_id = &config::get().yaml.archive.provider;
+    let entries =
+        provider::backend(provider_id)?.list(&provider::remote_path(provider_id, None)?.join(ZIP_FOLDER))?;
+
+    // archive path -> number of volumes, 0 when it wasn't split
+    let mut zip_paths: BTreeMap<PathBuf, usize> = BTreeMap::new();
+    for e in entries.into_iter().filter(|e| !e.is_dir) {
+        let path = PathBuf::from(ZIP_FOLDER).join(e.path);
+        if let Some((archive, _)) = zip::volume_of(&path) {
+            *zip_paths.entry(archive).or_default() += 1;
+        } else if path.extension().map(|x| x == "7z").unwrap_or(false) {
+            zip_paths.entry(path).or_default();
+        }
+    }
+
+    let tmp = tempfile::tempdir()?;
+
+    let mut errors = 0;
+    for (zip_path, volumes) in &zip_paths {
+        let catalog = catalog_path_in(db_folder, zip_path);
+        if catalog.exists() && !overwrite {
+            log::debug(&format!("{} already exists", catalog.to_string()));
+            continue;
+        }
+
+        match rebuild_one(zip_path, *volumes, &catalog, tmp.path()) {
+            Ok(_) => log::info(&format!("Rebuilt {}", catalog.to_string())),
+            Err(e) => {
+                log::error(&format!("Rebuilding {} failed: {}", zip_path.to_string(), e));
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        Err(anyhow!("{} of {} archives failed", errors, zip_paths.len()))
+    } else {
+        Ok(())
+    }
+}
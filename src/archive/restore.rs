@@ -0,0 +1,180 @@
+use super::remote_parts;
+use crate::config;
+use crate::crypto::Scheme;
+use crate::db::query::{self, Match, Query};
+use crate::fs::{self, IPathBuf};
+use crate::log;
+use crate::provider;
+use crate::zip;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug)]
+struct Zip {
+    hash: String,
+    scheme: Scheme,
+    volumes: usize,
+    files: Vec<PathBuf>,
+}
+
+fn group_by_zip(matches: Vec<Match>) -> Result<BTreeMap<PathBuf, Zip>> {
+    let mut zips: BTreeMap<PathBuf, Zip> = BTreeMap::new();
+
+    for m in matches {
+        let azure_path = m
+            .azure_path
+            .with_context(|| format!("no azure_path for {}", m.path.to_string()))?;
+        let hash = m.hash.with_context(|| format!("no hash for {}", &azure_path))?;
+        // catalogs without the tag predate versioned passwords
+        let scheme = match m.scheme {
+            Some(s) => Scheme::from_str(&s)?,
+            None => Scheme::Md5,
+        };
+        let volumes = match m.volumes {
+            Some(v) => v
+                .parse()
+                .with_context(|| format!("invalid volumes for {}", &azure_path))?,
+            None => 0,
+        };
+        zips.entry(PathBuf::from(azure_path))
+            .or_insert_with(|| Zip {
+                hash,
+                scheme,
+                volumes,
+                files: vec![],
+            })
+            .files
+            .push(m.path);
+    }
+
+    Ok(zips)
+}
+
+fn fetch_zips(zips: &BTreeMap<PathBuf, Zip>, tmp: &Path) -> Result<BTreeMap<PathBuf, PathBuf>> {
+    let provider_id = &config::get().yaml.archive.provider;
+    let buffer = &provider::get(provider_id)?.buffer;
+    let backend = provider::backend(provider_id)?;
+    let root = provider::remote_path(provider_id, None)?;
+    let tmp_path = |remote: &Path| tmp.join(remote.strip_prefix("/").unwrap_or(remote));
+
+    let mut locals = BTreeMap::new();
+    let mut remotes = vec![];
+    let mut split = vec![];
+
+    for (zip_path, zip) in zips {
+        let buffered = buffer.join(zip_path);
+        if buffered.exists() {
+            locals.insert(zip_path.clone(), buffered);
+            continue;
+        }
+
+        let local = tmp_path(&root.join(zip_path));
+
+        let buffered_volumes = zip::volumes(&buffered);
+        if !buffered_volumes.is_empty() {
+            zip::join(&buffered_volumes, &local)?;
+            locals.insert(zip_path.clone(), local);
+            continue;
+        }
+
+        let mut parts = vec![];
+        for part in remote_parts(zip_path, zip.volumes) {
+            let remote = root.join(&part);
+            // pulling skips missing files silently
+            if !backend.exists(&remote)? {
+                return Err(anyhow!("{} not found on {}", remote.to_string(), provider_id));
+            }
+            parts.push(remote);
+        }
+        if zip.volumes > 0 {
+            split.push((
+                parts.iter().map(|p| tmp_path(p)).collect::<Vec<_>>(),
+                local.clone(),
+            ));
+        }
+        locals.insert(zip_path.clone(), local);
+        remotes.append(&mut parts);
+    }
+
+    if !remotes.is_empty() {
+        backend.get_many(&remotes, tmp)?;
+    }
+
+    for (volumes, local) in split {
+        zip::join(&volumes, &local)?;
+        for volume in &volumes {
+            fs::remove_file(volume)?;
+        }
+    }
+
+    Ok(locals)
+}
+
+fn restore_zip(zip: &Path, expected: &Zip, tmp: &Path, to: &Path) -> Result<()> {
+    let actual_hash = fs::md5(zip)?;
+    if actual_hash != expected.hash {
+        return Err(anyhow!(
+            "hash mismatch for {}: expected {}, got {}",
+            zip.to_string(),
+            expected.hash,
+            actual_hash
+        ));
+    }
+
+    let password = config::get().crypto_password()?;
+    let extracted = tmp.join("extracted");
+    fs::remove_dir_all(&extracted)?;
+    zip::extract_with(expected.scheme, password, zip, &extracted)?;
+
+    for file in &expected.files {
+        let from = extracted.join(file);
+        if !from.exists() {
+            return Err(anyhow!("{} not found in {}", file.to_string(), zip.to_string()));
+        }
+        fs::copy(&from, &to.join(file))?;
+    }
+
+    fs::remove_dir_all(&extracted)?;
+
+    Ok(())
+}
+
+pub fn restore(pattern: &str, to: &Path, db_folder: &Path) -> Result<()> {
+    let query = Query::new(Some(pattern), None, None, None, None, None)?;
+    let matches = query::run(db_folder, &query)?;
+    if matches.is_empty() {
+        return Err(anyhow!("no archived file matches {}", pattern));
+    }
+
+    let zips = group_by_zip(matches)?;
+    let n_files = zips.values().map(|z| z.files.len()).sum::<usize>();
+    log::debug(&format!("{} files to restore from {} zips", n_files, zips.len()));
+
+    std::fs::create_dir_all(to)?;
+    let tmp = tempfile::Builder::new().prefix(".storm_restore").tempdir_in(to)?;
+
+    let locals = fetch_zips(&zips, tmp.path())?;
+
+    let mut errors = 0;
+    for (zip_path, zip) in &zips {
+        let local = locals.get(zip_path).context("zip not fetched")?;
+        match restore_zip(local, zip, tmp.path(), to) {
+            Ok(_) => log::info(&format!(
+                "Restored {} files from {}",
+                zip.files.len(),
+                zip_path.to_string()
+            )),
+            Err(e) => {
+                log::error(&format!("Restoring from {} failed: {}", zip_path.to_string(), e));
+                errors += 1;
+            }
+        }
+    }
+
+    if errors > 0 {
+        Err(anyhow!("{} archives failed", errors))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,368 @@
+use super::{pull_archive, ZIP_FOLDER};
+use crate::config;
+use crate::crypto::Scheme;
+use crate::db::{self, Db};
+use crate::fs::{self, IPathBuf};
+use crate::log;
+use crate::provider;
+use crate::zip;
+use anyhow::{Context, Result};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+/// An archive as recorded by its catalog
+#[derive(Debug)]
+struct Expected {
+    catalog: PathBuf,
+    hash: Option<String>,
+    size: Option<u64>,
+    scheme: Scheme,
+    volumes: usize,
+}
+
+/// An archive as found on the remote or in the provider buffer. Volumes of
+/// a split archive are listed together, with no md5
+#[derive(Debug, Default)]
+struct Listed {
+    size: u64,
+    md5: Option<String>,
+    buffered: bool,
+    volumes: usize,
+}
+
+pub enum Problem {
+    Missing(PathBuf),
+    WrongSize(PathBuf, u64, u64),
+    WrongHash(PathBuf, String, String),
+    Corrupt(PathBuf, String),
+    Unlisted(PathBuf),
+}
+
+impl Display for Problem {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let txt = match self {
+            Problem::Missing(path) => format!("missing\t{}", path.to_string()),
+            Problem::WrongSize(path, expected, actual) => {
+                format!("corrupt\t{}\tsize {} -> {}", path.to_string(), expected, actual)
+            }
+            Problem::WrongHash(path, expected, actual) => {
+                format!("corrupt\t{}\thash {} -> {}", path.to_string(), expected, actual)
+            }
+            Problem::Corrupt(path, msg) => format!("corrupt\t{}\t{}", path.to_string(), msg),
+            Problem::Unlisted(path) => format!("unlisted\t{}", path.to_string()),
+        };
+        fmt.write_str(&txt)
+    }
+}
+
+fn from_catalog(catalog: PathBuf, db: &Db) -> Result<Option<(PathBuf, Expected)>> {
+    let azure_path = match db.tag("azure_path") {
+        Some(p) => PathBuf::from(p),
+        None => return Ok(None),
+    };
+    let size = match db.tag(db::SIZE_TAG) {
+        Some(s) => Some(s.parse::<u64>().with_context(|| format!("invalid size {}", s))?),
+        None => None,
+    };
+    // catalogs without the tag predate versioned passwords
+    let scheme = match db.tag(db::PASSWORD_SCHEME_TAG) {
+        Some(s) => Scheme::from_str(s)?,
+        None => Scheme::Md5,
+    };
+    let volumes = match db.tag(db::VOLUMES_TAG) {
+        Some(v) => v
+            .parse::<usize>()
+            .with_context(|| format!("invalid volumes {}", v))?,
+        None => 0,
+    };
+
+    Ok(Some((
+        azure_path,
+        Expected {
+            catalog,
+            hash: db.tag("hash").cloned(),
+            size,
+            scheme,
+            volumes,
+        },
+    )))
+}
+
+fn list_remote() -> Result<BTreeMap<PathBuf, Listed>> {
+    let provider_id = &config::get().yaml.archive.provider;
+    let entries =
+        provider::backend(provider_id)?.list(&provider::remote_path(provider_id, None)?.join(ZIP_FOLDER))?;
+
+    let mut listed: BTreeMap<PathBuf, Listed> = BTreeMap::new();
+    for e in entries.into_iter().filter(|e| !e.is_dir) {
+        let path = PathBuf::from(ZIP_FOLDER).join(&e.path);
+        let size = e.size.max(0) as u64;
+        match zip::volume_of(&path) {
+            Some((archive, _)) => {
+                let l = listed.entry(archive).or_default();
+                l.size += size;
+                l.volumes += 1;
+            }
+            None => {
+                let md5 = e.md5().map(|h| h.to_lowercase());
+                listed.insert(
+                    path,
+                    Listed {
+                        size,
+                        md5,
+                        ..Default::default()
+                    },
+                );
+            }
+        }
+    }
+
+    Ok(listed)
+}
+
+/// Archives that haven't been uploaded yet are checked in the buffer instead
+fn list_buffered(
+    expected: &BTreeMap<PathBuf, Expected>,
+    listed: &mut BTreeMap<PathBuf, Listed>,
+) -> Result<()> {
+    let provider_id = &config::get().yaml.archive.provider;
+    let buffer = &provider::get(provider_id)?.buffer;
+
+    for zip_path in expected.keys() {
+        let local = buffer.join(zip_path);
+        let volumes = zip::volumes(&local);
+        if listed.contains_key(zip_path) || (!local.exists() && volumes.is_empty()) {
+            continue;
+        }
+        log::info(&format!("{} is not uploaded yet", zip_path.to_string()));
+
+        let l = if volumes.is_empty() {
+            Listed {
+                size: std::fs::metadata(&local)?.len(),
+                md5: Some(fs::md5(&local)?),
+                buffered: true,
+                volumes: 0,
+            }
+        } else {
+            let mut size = 0;
+            for volume in &volumes {
+                size += std::fs::metadata(volume)?.len();
+            }
+            Listed {
+                size,
+                md5: None,
+                buffered: true,
+                volumes: volumes.len(),
+            }
+        };
+        listed.insert(zip_path.clone(), l);
+    }
+
+    Ok(())
+}
+
+fn check(expected: &BTreeMap<PathBuf, Expected>, listed: &BTreeMap<PathBuf, Listed>) -> Vec<Problem> {
+    let mut problems = vec![];
+
+    for (zip_path, e) in expected {
+        let l = match listed.get(zip_path) {
+            Some(l) => l,
+            None => {
+                problems.push(Problem::Missing(zip_path.clone()));
+                continue;
+            }
+        };
+        if e.volumes != l.volumes {
+            let msg = format!("volumes {} -> {}", e.volumes, l.volumes);
+            problems.push(Problem::Corrupt(zip_path.clone(), msg));
+            continue;
+        }
+        if let Some(size) = e.size {
+            if size != l.size {
+                problems.push(Problem::WrongSize(zip_path.clone(), size, l.size));
+                continue;
+            }
+        }
+        if let (Some(expected_hash), Some(actual_hash)) = (&e.hash, &l.md5) {
+            if expected_hash != actual_hash {
+                problems.push(Problem::WrongHash(
+                    zip_path.clone(),
+                    expected_hash.clone(),
+                    actual_hash.clone(),
+                ));
+            }
+        }
+    }
+
+    for zip_path in listed.keys() {
+        if !expected.contains_key(zip_path) {
+            problems.push(Problem::Unlisted(zip_path.clone()));
+        }
+    }
+
+    problems
+}
+
+/// Downloads the archive, checks its hash when the remote couldn't provide
+/// one and tests it with the catalog's password scheme
+fn test_zip(zip_path: &Path, e: &Expected, listed: &Listed, tmp: &Path) -> Result<()> {
+    let provider_id = &config::get().yaml.archive.provider;
+    let buffered = provider::get(provider_id)?.buffer.join(zip_path);
+    let local = tmp.join(zip_path.file_name().context("no filename")?);
+    let local = match (listed.buffered, listed.volumes) {
+        (true, 0) => buffered,
+        (true, _) => {
+            zip::join(&zip::volumes(&buffered), &local)?;
+            local
+        }
+        (false, volumes) => {
+            pull_archive(zip_path, volumes, &local)?;
+            local
+        }
+    };
+
+    let result = check_zip(e, listed, &local);
+
+    if !listed.buffered || listed.volumes > 0 {
+        fs::remove_file(&local)?;
+    }
+
+    result
+}
+
+fn check_zip(e: &Expected, listed: &Listed, local: &Path) -> Result<()> {
+    if let (Some(expected_hash), None) = (&e.hash, &listed.md5) {
+        let actual_hash = fs::md5(local)?;
+        if &actual_hash != expected_hash {
+            return Err(anyhow!("hash {} -> {}", expected_hash, actual_hash));
+        }
+    }
+
+    let password = config::get().crypto_password()?;
+    zip::test(e.scheme, password, local).map_err(|_| anyhow!("7z test failed"))
+}
+
+pub fn verify(db_folder: &Path, download: bool) -> Result<()> {
+    let mut expected = BTreeMap::new();
+    for (catalog, db) in db::read_all(db_folder)? {
+        match from_catalog(catalog.clone(), &db)? {
+            Some((zip_path, e)) => {
+                expected.insert(zip_path, e);
+            }
+            None => log::debug(&format!("{} describes no archive", catalog.to_string())),
+        }
+    }
+
+    let mut listed = list_remote()?;
+    list_buffered(&expected, &mut listed)?;
+
+    let mut problems = check(&expected, &listed);
+
+    if download {
+        let tmp = tempfile::tempdir()?;
+        let broken = problems
+            .iter()
+            .filter_map(|p| match p {
+                Problem::Missing(p)
+                | Problem::WrongSize(p, _, _)
+                | Problem::WrongHash(p, _, _)
+                | Problem::Corrupt(p, _) => Some(p.clone()),
+                Problem::Unlisted(_) => None,
+            })
+            .collect::<Vec<_>>();
+
+        for (zip_path, e) in &expected {
+            if broken.contains(zip_path) {
+                continue;
+            }
+            let l = listed.get(zip_path).context("archive not listed")?;
+            log::info(&format!("Testing {}", zip_path.to_string()));
+            if let Err(err) = test_zip(zip_path, e, l, tmp.path()) {
+                problems.push(Problem::Corrupt(zip_path.clone(), err.to_string()));
+            }
+        }
+    }
+
+    for problem in &problems {
+        match problem {
+            Problem::Missing(p)
+            | Problem::WrongSize(p, _, _)
+            | Problem::WrongHash(p, _, _)
+            | Problem::Corrupt(p, _) => {
+                let catalog = &expected.get(p).context("no catalog")?.catalog;
+                println!("{}\t{}", problem, catalog.to_string());
+            }
+            Problem::Unlisted(_) => println!("{}", problem),
+        }
+    }
+
+    if problems.is_empty() {
+        log::info(&format!("{} archives verified", expected.len()));
+        Ok(())
+    } else {
+        Err(anyhow!(
+            "{} problems found in {} archives",
+            problems.len(),
+            expected.len()
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn expected(hash: &str, size: Option<u64>) -> Expected {
+        Expected {
+            catalog: PathBuf::from("db/a.storm.txt"),
+            hash: Some(hash.into()),
+            size,
+            scheme: Scheme::Md5,
+            volumes: 0,
+        }
+    }
+
+    fn listed(size: u64, md5: Option<&str>) -> Listed {
+        Listed {
+            size,
+            md5: md5.map(String::from),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_check() {
+        let mut e = BTreeMap::new();
+        e.insert(PathBuf::from("ByTimestamp/ok.7z"), expected("aa", Some(10)));
+        e.insert(PathBuf::from("ByTimestamp/nohash.7z"), expected("aa", None));
+        e.insert(PathBuf::from("ByTimestamp/size.7z"), expected("aa", Some(10)));
+        e.insert(PathBuf::from("ByTimestamp/hash.7z"), expected("aa", Some(10)));
+        e.insert(PathBuf::from("ByTimestamp/missing.7z"), expected("aa", Some(10)));
+        let mut split = expected("aa", Some(20));
+        split.volumes = 2;
+        e.insert(PathBuf::from("ByTimestamp/split.7z"), split);
+
+        let mut l = BTreeMap::new();
+        l.insert(PathBuf::from("ByTimestamp/ok.7z"), listed(10, Some("aa")));
+        l.insert(PathBuf::from("ByTimestamp/nohash.7z"), listed(5, None));
+        l.insert(PathBuf::from("ByTimestamp/size.7z"), listed(11, Some("aa")));
+        l.insert(PathBuf::from("ByTimestamp/hash.7z"), listed(10, Some("bb")));
+        l.insert(PathBuf::from("ByTimestamp/extra.7z"), listed(10, None));
+        let mut split = listed(20, None);
+        split.volumes = 1;
+        l.insert(PathBuf::from("ByTimestamp/split.7z"), split);
+
+        let problems = check(&e, &l).iter().map(|p| p.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            problems,
+            vec![
+                "corrupt\tByTimestamp/hash.7z\thash aa -> bb",
+                "missing\tByTimestamp/missing.7z",
+                "corrupt\tByTimestamp/size.7z\tsize 10 -> 11",
+                "corrupt\tByTimestamp/split.7z\tvolumes 2 -> 1",
+                "unlisted\tByTimestamp/extra.7z",
+            ]
+        );
+    }
+}
@@ -19,9 +19,9 @@ pub(super) enum SkipReason {
 pub(super) fn should_process(
     path: &Path,
     kb: u32,
+    md5: Option<&str>,
     denylist: &[Regex],
-    zip_kb: u32,
-    hashes: &[u64],
+    hashes: &db::Hashes,
 ) -> SkipReason {
     let max_file_kb = config::get().yaml.archive.max_file_kb;
     if kb > max_file_kb {
@@ -29,9 +29,8 @@ pub(super) fn should_process(
     }
 
     let max_zip_kb = config::get().yaml.archive.max_zip_kb;
-    let new_zip_kb = zip_kb + kb;
-    if new_zip_kb > max_zip_kb {
-        return SkipReason::ExceedsZipSize(new_zip_kb);
+    if kb > max_zip_kb {
+        return SkipReason::ExceedsZipSize(kb);
     }
 
     for regex in denylist {
@@ -40,7 +39,7 @@ pub(super) fn should_process(
         }
     }
 
-    if db::has(path, hashes) {
+    if db::has(path, md5, hashes) {
         return SkipReason::AlreadyUploaded;
     }
 
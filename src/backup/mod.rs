@@ -4,6 +4,7 @@ mod work;
 use crate::config;
 use crate::fs::IPathBuf;
 use crate::log;
+use crate::plan;
 use anyhow::{Context, Result};
 use regex::Regex;
 use skip::{should_process, SkipReason};
@@ -32,6 +33,10 @@ fn get_filepaths(froms: &[PathBuf]) -> Result<HashMap<usize, Vec<PathBuf>>> {
 
         for entry in WalkDir::new(local).into_iter().filter_map(|e| e.ok()) {
             let reason = should_process(&entry, max_kb, &denylist);
+            if !matches!(reason, SkipReason::Directory) {
+                plan::decision(entry.path(), &reason);
+            }
+
             match reason {
                 SkipReason::Directory => (),
                 SkipReason::NoSkip => {
@@ -1,11 +1,12 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use super::skip::{self, SkipReason};
 use crate::config;
 use crate::format::{self};
 use crate::fs::{self, IPathBuf};
 use crate::log;
-use crate::provider;
+use crate::plan;
+use crate::provider::{self, Entry};
 use crate::rclone;
 use crate::smalldate;
 use anyhow::{Context, Result};
@@ -25,6 +26,9 @@ pub fn set_camera_buffer() -> Result<()> {
 
         let format = format::get_format(path);
         let reason = skip::should_process(&entry, &format);
+        if !matches!(reason, SkipReason::Directory) {
+            plan::decision(entry.path(), &reason);
+        }
 
         match reason {
             SkipReason::NoSkip => {}
@@ -66,10 +70,24 @@ pub fn set_camera_buffer() -> Result<()> {
     Ok(())
 }
 
+/// Files of a folder are uploaded flattened, as `<folder>_<filename>` at the root
+fn is_uploaded(remote_entries: &[Entry], folder_name: &str) -> bool {
+    let prefix = format!("{}_", folder_name);
+    remote_entries.iter().any(|e| {
+        !e.is_dir
+            && e.path.parent().map(|p| p == Path::new("")).unwrap_or(true)
+            && e.path.to_string().starts_with(&prefix)
+    })
+}
+
 pub fn remove_backed_pictures() -> Result<()> {
     let ref_provider_id = &config::get().yaml.camera_backup.ref_provider;
     let local_intermediate = config::get().yaml.camera_backup.local_intermediate.clone();
 
+    let remote = provider::remote_path(ref_provider_id, None)?;
+    // uploads are at the root, so there is no need to look further or hash anything
+    let remote_entries = rclone::lsjson(ref_provider_id, Some(remote), rclone::ListOptions::SHALLOW)?;
+
     for entry in WalkDir::new(local_intermediate)
         .min_depth(1)
         .max_depth(1)
@@ -88,9 +106,7 @@ pub fn remove_backed_pictures() -> Result<()> {
             .to_string_lossy()
             .to_string();
 
-        let already_uploaded = rclone::has_prefixed_files(ref_provider_id, None, &folder_name)?;
-
-        if already_uploaded {
+        if is_uploaded(&remote_entries, &folder_name) {
             fs::remove_dir_all(path)?;
         } else {
             log::info(&format!("{} doesn't seem to be uploaded", &folder_name));
@@ -99,3 +115,27 @@ pub fn remove_backed_pictures() -> Result<()> {
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn entry(path: &str) -> Entry {
+        Entry {
+            path: PathBuf::from(path),
+            size: 1,
+            mod_time: String::new(),
+            is_dir: false,
+            hashes: HashMap::new(),
+        }
+    }
+
+    #[test]
+    fn test_is_uploaded() {
+        let entries = vec![entry("2k1b_IMG 0001.jpg"), entry("old/2k1c_IMG_0002.jpg")];
+        assert!(is_uploaded(&entries, "2k1b"));
+        assert!(!is_uploaded(&entries, "2k1"));
+        assert!(!is_uploaded(&entries, "2k1c"));
+    }
+}
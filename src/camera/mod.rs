@@ -5,6 +5,7 @@ mod work;
 use crate::format::{self, Format};
 use crate::fs::IPathBuf;
 use crate::log;
+use crate::plan::{self, Action};
 use crate::{config, normalize, smalldate};
 use anyhow::Context;
 use anyhow::Result;
@@ -22,6 +23,9 @@ pub use backup::set_camera_buffer;
 
 fn normalize(froms: &[PathBuf], folder_id: &str) -> Result<()> {
     for from in froms {
+        if plan::skip(Action::Normalize { path: from.clone() }) {
+            continue;
+        }
         normalize::pictures(from, folder_id)?;
     }
     Ok(())
@@ -37,6 +41,10 @@ fn get_filepaths(froms: &[PathBuf]) -> Result<HashMap<usize, Vec<PathBuf>>> {
         for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
             let format = format::get_format(entry.path());
             let reason = skip::should_process(&entry, &format);
+            if !matches!(reason, SkipReason::Directory) {
+                plan::decision(entry.path(), &reason);
+            }
+
             match reason {
                 SkipReason::Directory => (),
                 SkipReason::NoSkip => {
@@ -10,6 +10,7 @@ pub(super) struct WorkerResult(pub usize, pub Option<Error>);
 
 pub(super) fn process(backup: &CameraBackupDef, relative: &Path) -> Result<()> {
     let password = config::get().crypto_password()?;
+    let scheme = config::get().password_scheme()?;
 
     let entry_path = backup.from.join(relative);
     let relative_with_base = &backup.to.join(relative);
@@ -23,8 +24,10 @@ pub(super) fn process(backup: &CameraBackupDef, relative: &Path) -> Result<()> {
     compress::compress(&entry_path, &low_unzipped_path, Quality::Low)?;
     compress::compress(&entry_path, &high_unzipped_path, Quality::High)?;
 
-    zip::create(password, &low_unzipped_path, &low_zipped_path)?;
-    zip::create(password, &high_unzipped_path, &high_zipped_path)?;
+    zip::create(scheme, password, &low_unzipped_path, &low_zipped_path)?;
+    zip::create(scheme, password, &high_unzipped_path, &high_zipped_path)?;
+    zip::split_for(&backup.low_zipped, &low_zipped_path)?;
+    zip::split_for(&backup.high_zipped, &high_zipped_path)?;
 
     fs::remove_file(&high_unzipped_path)?;
     fs::mv(&entry_path, &high_unzipped_path)?;
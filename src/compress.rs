@@ -2,6 +2,7 @@ use crate::exif::{self, CompressionInvariantProps, Props};
 use crate::format::{self, Format};
 use crate::fs::{self, IPathBuf};
 use crate::log;
+use crate::plan::{self, Action};
 use crate::shell;
 use anyhow::{self, Result};
 use std::path::Path;
@@ -167,6 +168,18 @@ pub fn compress(from: &Path, to: &Path, quality: Quality) -> Result<()> {
         return Ok(());
     }
 
+    let quality_str = match quality {
+        Quality::High => "high",
+        Quality::Low => "low",
+    };
+    if plan::skip(Action::Compress {
+        from: from.into(),
+        to: to.into(),
+        quality: quality_str.into(),
+    }) {
+        return Ok(());
+    }
+
     log::info(&format!("compress {}", from.to_string()));
     fs::remove_file(to)?;
 
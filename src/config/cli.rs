@@ -33,8 +33,19 @@ pub enum Command {
         txt: String,
     },
     UploadTelegramBuffer,
+    FetchTelegramFiles {
+        pattern: String,
+        #[clap(long)]
+        to: Option<PathBuf>,
+        #[clap(long, allow_hyphen_values = true)]
+        forward_to: Option<String>,
+        #[clap(long)]
+        copy: bool,
+    },
     Password {
         filename: PathBuf,
+        #[clap(long)]
+        scheme: Option<u8>,
     },
     DbFromFilepaths {
         path_to_list: PathBuf,
@@ -57,6 +68,78 @@ pub enum Command {
     },
     SetBackupCameraBuffer,
     RemoveBackedPictures,
+    Restore {
+        pattern: String,
+        to: PathBuf,
+        #[clap(long)]
+        db_folder: Option<PathBuf>,
+    },
+    Verify {
+        #[clap(long)]
+        download: bool,
+        #[clap(long)]
+        db_folder: Option<PathBuf>,
+    },
+    Rekey {
+        #[clap(long, ignore_case = true)]
+        provider: Option<String>,
+        #[clap(long)]
+        local: Option<PathBuf>,
+        #[clap(long)]
+        checkpoint: Option<PathBuf>,
+    },
+    Db {
+        #[clap(subcommand)]
+        cmd: DbCommand,
+    },
+}
+
+#[derive(Subcommand, Debug, Clone)]
+pub enum DbCommand {
+    Query {
+        #[clap(long)]
+        glob: Option<String>,
+        #[clap(long)]
+        regex: Option<String>,
+        #[clap(long)]
+        from: Option<String>,
+        #[clap(long)]
+        to: Option<String>,
+        #[clap(long)]
+        min_kb: Option<u32>,
+        #[clap(long)]
+        max_kb: Option<u32>,
+        #[clap(long)]
+        db_folder: Option<PathBuf>,
+    },
+    Convert {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Diff {
+        old: PathBuf,
+        new: PathBuf,
+    },
+    Merge {
+        base: PathBuf,
+        other: PathBuf,
+        #[clap(long)]
+        output: PathBuf,
+        #[clap(long, default_value = "newest")]
+        policy: String,
+    },
+    Fsck {
+        #[clap(long)]
+        db_folder: Option<PathBuf>,
+        #[clap(long)]
+        fix: bool,
+    },
+    Rebuild {
+        #[clap(long)]
+        db_folder: Option<PathBuf>,
+        #[clap(long)]
+        overwrite: bool,
+    },
 }
 
 /// Simple program to greet a person
@@ -71,6 +154,12 @@ pub struct ClapConfig {
 
     #[clap(short, long)]
     pub ignore_rotation: bool,
+
+    #[clap(long)]
+    pub dry_run: bool,
+
+    #[clap(long)]
+    pub json: bool,
 }
 
 impl ClapConfig {
@@ -1,13 +1,16 @@
 mod cli;
 mod env;
+mod secret;
 pub mod yaml;
 
-use anyhow::{Context, Result};
-pub use cli::{ClapConfig, Command};
+use anyhow::Result;
+pub use cli::{ClapConfig, Command, DbCommand};
 use env::EnvConfig;
 use once_cell::sync::OnceCell;
+use secret::Secret;
 use yaml::YamlConfig;
 
+use crate::crypto::Scheme;
 use crate::log;
 
 static INSTANCE: OnceCell<Config> = OnceCell::new();
@@ -15,6 +18,9 @@ static INSTANCE: OnceCell<Config> = OnceCell::new();
 pub struct Config {
     pub yaml: YamlConfig,
     pub clap: ClapConfig,
+    crypto_password: OnceCell<String>,
+    previous_crypto_password: OnceCell<String>,
+    telegram_token: OnceCell<String>,
 }
 
 impl Config {
@@ -28,7 +34,13 @@ impl Config {
             ));
             YamlConfig::default()
         });
-        Self { yaml, clap }
+        Self {
+            yaml,
+            clap,
+            crypto_password: OnceCell::new(),
+            previous_crypto_password: OnceCell::new(),
+            telegram_token: OnceCell::new(),
+        }
     }
 
     pub fn cmd(&self) -> &Command {
@@ -36,7 +48,55 @@ impl Config {
     }
 
     pub fn crypto_password(&self) -> Result<&String> {
-        self.yaml.crypto.password.as_ref().context("password not set")
+        self.crypto_password.get_or_try_init(|| {
+            let crypto = &self.yaml.crypto;
+            secret::resolve(
+                "password",
+                vec![
+                    crypto.password.as_deref().map(Secret::Plain),
+                    crypto.password_env.as_deref().map(Secret::Env),
+                    crypto.password_file.as_deref().map(Secret::File),
+                    crypto.password_cmd.as_deref().map(Secret::Cmd),
+                ],
+            )
+        })
+    }
+
+    pub fn telegram_token(&self) -> Result<&String> {
+        self.telegram_token.get_or_try_init(|| {
+            let telegram = &self.yaml.telegram;
+            secret::resolve(
+                "telegram token",
+                vec![
+                    telegram.token.as_deref().map(Secret::Plain),
+                    telegram.token_env.as_deref().map(Secret::Env),
+                    telegram.token_file.as_deref().map(Secret::File),
+                    telegram.token_cmd.as_deref().map(Secret::Cmd),
+                ],
+            )
+        })
+    }
+
+    pub fn previous_crypto_password(&self) -> Result<&String> {
+        self.previous_crypto_password.get_or_try_init(|| {
+            let crypto = &self.yaml.crypto;
+            secret::resolve(
+                "previous_password",
+                vec![
+                    crypto.previous_password.as_deref().map(Secret::Plain),
+                    crypto.previous_password_env.as_deref().map(Secret::Env),
+                    crypto.previous_password_file.as_deref().map(Secret::File),
+                    crypto.previous_password_cmd.as_deref().map(Secret::Cmd),
+                ],
+            )
+        })
+    }
+
+    pub fn password_scheme(&self) -> Result<Scheme> {
+        match self.yaml.crypto.scheme {
+            Some(v) => Scheme::from_version(v),
+            None => Ok(Scheme::LATEST),
+        }
     }
 }
 
@@ -44,6 +104,20 @@ pub fn get() -> &'static Config {
     INSTANCE.get().expect("config not initialized")
 }
 
+/// Defaults, for tests of code that reads the config
+#[cfg(test)]
+pub fn setup_default() {
+    use clap::Parser;
+
+    INSTANCE.get_or_init(|| Config {
+        yaml: YamlConfig::default(),
+        clap: ClapConfig::parse_from(["storm", "create-archive-zips"]),
+        crypto_password: OnceCell::new(),
+        previous_crypto_password: OnceCell::new(),
+        telegram_token: OnceCell::new(),
+    });
+}
+
 pub fn setup() -> Result<()> {
     let config = Config::new();
     INSTANCE
@@ -0,0 +1,88 @@
+use crate::fs::{self, IPathBuf};
+use crate::shell;
+use anyhow::{Context, Result};
+use std::path::Path;
+
+pub enum Secret<'a> {
+    Plain(&'a str),
+    Env(&'a str),
+    File(&'a Path),
+    Cmd(&'a str),
+}
+
+impl Secret<'_> {
+    fn read(&self) -> Result<String> {
+        let raw = match self {
+            Secret::Plain(value) => value.to_string(),
+            Secret::Env(var) => {
+                std::env::var(var).with_context(|| format!("unable to read env var {}", var))?
+            }
+            Secret::File(path) => {
+                let lines = fs::read_lines(path)?.collect::<Vec<_>>();
+                lines.join("\n")
+            }
+            Secret::Cmd(cmd) => {
+                let out = shell::out("sh", &["-c", cmd])
+                    .with_context(|| format!("unable to run {}", cmd))?
+                    .res
+                    .context("no command output")?;
+                out.stdout
+            }
+        };
+
+        let value = raw.trim_end_matches(&['\r', '\n'][..]).to_owned();
+        if value.is_empty() {
+            let source = match self {
+                Secret::File(path) => path.to_string(),
+                Secret::Env(x) | Secret::Cmd(x) => x.to_string(),
+                Secret::Plain(_) => "plain value".into(),
+            };
+            return Err(anyhow!("empty secret from {}", source));
+        }
+
+        Ok(value)
+    }
+}
+
+pub fn resolve(name: &str, candidates: Vec<Option<Secret>>) -> Result<String> {
+    let mut secrets = candidates.into_iter().flatten();
+    let secret = secrets.next().with_context(|| format!("{} not set", name))?;
+    if secrets.next().is_some() {
+        return Err(anyhow!("{} is set in more than one way", name));
+    }
+    secret.read()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve() -> Result<()> {
+        assert_eq!(
+            resolve("password", vec![Some(Secret::Plain("foo")), None])?,
+            "foo"
+        );
+
+        std::env::set_var("STORM_TEST_SECRET", "bar\n");
+        assert_eq!(
+            resolve("password", vec![None, Some(Secret::Env("STORM_TEST_SECRET"))])?,
+            "bar"
+        );
+
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("secret");
+        std::fs::write(&path, "baz\n")?;
+        assert_eq!(resolve("password", vec![Some(Secret::File(&path))])?, "baz");
+
+        assert!(resolve("password", vec![None, None]).is_err());
+        assert!(resolve(
+            "password",
+            vec![Some(Secret::Plain("foo")), Some(Secret::File(&path))]
+        )
+        .is_err());
+        assert!(resolve("password", vec![Some(Secret::Plain(""))]).is_err());
+
+        Ok(())
+    }
+}
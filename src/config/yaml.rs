@@ -45,8 +45,19 @@ pub struct CameraBackup {
 #[serde(deny_unknown_fields)]
 pub struct Telegram {
     pub chat_id: String,
-    pub token: String,
+    pub token: Option<String>,
+    pub token_env: Option<String>,
+    pub token_file: Option<PathBuf>,
+    pub token_cmd: Option<String>,
     pub db_path: PathBuf,
+    /// Bot API base URL, https://api.telegram.org by default
+    pub api_url: Option<String>,
+    /// Also post photos and videos as albums, one per folder, so the chat
+    /// can be browsed. The originals are still sent as documents
+    #[serde(default)]
+    pub media_groups: bool,
+    /// Messages sent to a chat per minute, 20 by default
+    pub messages_per_minute: Option<u32>,
 }
 
 #[derive(Deserialize, Clone, Debug)]
@@ -71,12 +82,20 @@ pub struct Backup {
 #[derive(Deserialize)]
 #[serde(deny_unknown_fields)]
 pub struct Provider {
+    /// rclone, local or telegram. Defaults to telegram for the `telegram`
+    /// provider and to rclone for the others
+    #[serde(rename = "type")]
+    pub kind: Option<String>,
     pub buffer: PathBuf,
     pub rclone: Option<String>,
     #[serde(default = "default_single_folder")]
     pub single_folder: bool,
     pub extra_rclone_push_args: Option<Vec<String>>,
     pub remote_path_fallback: Option<PathBuf>,
+    /// Folder standing in for the remote of a local provider
+    pub root: Option<PathBuf>,
+    /// Archives bigger than this are split into `.7z.001`, `.7z.002`... volumes
+    pub max_object_kb: Option<u32>,
 }
 
 #[derive(Deserialize, Default)]
@@ -84,6 +103,14 @@ pub struct Provider {
 #[serde(deny_unknown_fields)]
 pub struct Crypto {
     pub password: Option<String>,
+    pub password_env: Option<String>,
+    pub password_file: Option<PathBuf>,
+    pub password_cmd: Option<String>,
+    pub previous_password: Option<String>,
+    pub previous_password_env: Option<String>,
+    pub previous_password_file: Option<PathBuf>,
+    pub previous_password_cmd: Option<String>,
+    pub scheme: Option<u8>,
 }
 
 #[derive(Deserialize)]
@@ -93,6 +120,21 @@ pub struct Parallelism {
     pub workers: u8,
 }
 
+#[derive(Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Exiftool {
+    pub timeout_secs: u64,
+}
+
+#[derive(Deserialize)]
+#[serde(default)]
+#[serde(deny_unknown_fields)]
+pub struct Zip {
+    /// Either `7z`, for the binary, or `native`
+    pub backend: String,
+}
+
 #[derive(Default, Deserialize)]
 #[serde(default)]
 #[serde(deny_unknown_fields)]
@@ -122,6 +164,21 @@ pub struct Archive {
     pub provider: String,
     pub db_folder: Option<PathBuf>,
     pub tmp_buffer: Option<PathBuf>,
+    /// Either `directory` or `month`, to keep such files in the same zip
+    pub group_by: Option<String>,
+}
+
+/// Where run notifications go
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+#[serde(deny_unknown_fields)]
+pub enum Sink {
+    /// A message to the configured chat
+    Telegram,
+    /// The event POSTed as JSON
+    Webhook { url: String },
+    /// Run with `sh -c`, the event as JSON on stdin
+    Command { cmd: String },
 }
 
 #[derive(Deserialize, Default)]
@@ -135,8 +192,11 @@ pub struct YamlConfig {
     pub cloud: Storm,
     pub tasker: Tasker,
     pub parallelism: Parallelism,
+    pub exiftool: Exiftool,
+    pub zip: Zip,
     pub telegram: Telegram,
     pub backup: Backup,
+    pub notifications: Vec<Sink>,
 }
 
 impl YamlConfig {
@@ -173,6 +233,25 @@ impl Default for Parallelism {
     }
 }
 
+impl Default for Exiftool {
+    fn default() -> Self {
+        Self { timeout_secs: 60 }
+    }
+}
+
+impl Default for Zip {
+    fn default() -> Self {
+        let backend = if cfg!(feature = "native-7z") {
+            "native"
+        } else {
+            "7z"
+        };
+        Self {
+            backend: backend.into(),
+        }
+    }
+}
+
 fn default_low_unzipped() -> String {
     "gphotos".into()
 }
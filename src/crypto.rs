@@ -1,14 +1,100 @@
 use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use sha2::Sha256;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt::{self, Display};
+use std::sync::Mutex;
 use std::u128;
 
-pub fn gen_password(password: &str, filename: &str) -> Result<String> {
-    let base = format!("{}_{}\n", password, filename);
-    let hashed_bytes = md5::compute(base).0;
+const KDF_SALT: &[u8] = b"storm-archive-password-v2";
+// the defaults of argon2 0.4, which v2 passwords were first derived with.
+// Changing them makes every v2 archive unreadable
+const KDF_M_COST: u32 = 4096;
+const KDF_T_COST: u32 = 3;
+const KDF_P_COST: u32 = 1;
+
+static MASTER_KEYS: OnceCell<Mutex<HashMap<String, [u8; 32]>>> = OnceCell::new();
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scheme {
+    Md5 = 1,
+    Kdf = 2,
+}
+
+impl Scheme {
+    pub const LATEST: Scheme = Scheme::Kdf;
+
+    /// Newest first, so that detection prefers the current scheme
+    pub const ALL: [Scheme; 2] = [Scheme::Kdf, Scheme::Md5];
+
+    pub fn from_version(version: u8) -> Result<Self> {
+        match version {
+            1 => Ok(Scheme::Md5),
+            2 => Ok(Scheme::Kdf),
+            _ => Err(anyhow!("unknown password scheme: {}", version)),
+        }
+    }
+
+    pub fn from_str(txt: &str) -> Result<Self> {
+        let version = txt.trim().parse().context("password scheme is not a number")?;
+        Scheme::from_version(version)
+    }
+}
+
+impl Display for Scheme {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", *self as u8)
+    }
+}
+
+pub fn gen_password(scheme: Scheme, password: &str, filename: &str) -> Result<String> {
+    let hashed_bytes = match scheme {
+        Scheme::Md5 => md5_bytes(password, filename),
+        Scheme::Kdf => kdf_bytes(password, filename)?,
+    };
     let decimal = array_u8_to_decimal(hashed_bytes);
     let lowercase = format_radix(decimal, 36)?;
     Ok(lowercase.to_uppercase())
 }
 
+fn md5_bytes(password: &str, filename: &str) -> [u8; 16] {
+    let base = format!("{}_{}\n", password, filename);
+    md5::compute(base).0
+}
+
+fn master_key(password: &str) -> Result<[u8; 32]> {
+    let mut keys = MASTER_KEYS
+        .get_or_init(|| Mutex::new(HashMap::new()))
+        .lock()
+        .map_err(|_| anyhow!("unable to lock master keys"))?;
+
+    if let Some(key) = keys.get(password) {
+        return Ok(*key);
+    }
+
+    let mut key = [0u8; 32];
+    let params = Params::new(KDF_M_COST, KDF_T_COST, KDF_P_COST, Some(key.len()))
+        .map_err(|e| anyhow!("invalid kdf params: {}", e))?;
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(password.as_bytes(), KDF_SALT, &mut key)
+        .map_err(|e| anyhow!("unable to derive master key: {}", e))?;
+    keys.insert(password.into(), key);
+
+    Ok(key)
+}
+
+fn kdf_bytes(password: &str, filename: &str) -> Result<[u8; 16]> {
+    let key = master_key(password)?;
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).map_err(|e| anyhow!("invalid key: {}", e))?;
+    mac.update(filename.as_bytes());
+    let digest = mac.finalize().into_bytes();
+    let bytes = digest[..16].try_into()?;
+    Ok(bytes)
+}
+
 fn array_u8_to_decimal(arr: [u8; 16]) -> u128 {
     let mut d = 0;
     let base: u128 = 256;
@@ -34,3 +120,48 @@ pub fn format_radix(mut x: u128, radix: u128) -> Result<String> {
 
     Ok(result.into_iter().rev().collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_legacy_password() -> Result<()> {
+        let expected = {
+            let hashed = md5::compute("sw0rdf1sh_beach.jpg\n").0;
+            format_radix(array_u8_to_decimal(hashed), 36)?.to_uppercase()
+        };
+        assert_eq!(gen_password(Scheme::Md5, "sw0rdf1sh", "beach.jpg")?, expected);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kdf_password() -> Result<()> {
+        let a = gen_password(Scheme::Kdf, "sw0rdf1sh", "beach.jpg")?;
+        assert_eq!(a, gen_password(Scheme::Kdf, "sw0rdf1sh", "beach.jpg")?);
+        assert_ne!(a, gen_password(Scheme::Kdf, "sw0rdf1sh", "mountain.jpg")?);
+        assert_ne!(a, gen_password(Scheme::Kdf, "hunter2", "beach.jpg")?);
+        assert_ne!(a, gen_password(Scheme::Md5, "sw0rdf1sh", "beach.jpg")?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_kdf_known_answer() -> Result<()> {
+        let key = master_key("sw0rdf1sh")?;
+        let hex = key.iter().map(|b| format!("{:02x}", b)).collect::<String>();
+        assert_eq!(
+            hex,
+            "354d39445fc06c243d1209e13eabfdb88b638bf37f0689112e91e829174f6a9a"
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_scheme() -> Result<()> {
+        for scheme in Scheme::ALL {
+            assert_eq!(Scheme::from_str(&scheme.to_string())?, scheme);
+        }
+        assert!(Scheme::from_str("3").is_err());
+        Ok(())
+    }
+}
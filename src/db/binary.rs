@@ -0,0 +1,290 @@
+use super::file::File;
+use super::filemap::FileMap;
+use super::header::Header;
+use super::tree::{Tree, TreeNode};
+use super::Db;
+use crate::smalldate::SmallDate;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+
+const MAGIC: &[u8; 8] = b"STORMDB\0";
+const VERSION: u32 = 2;
+// without content hashes
+const VERSION_1: u32 = 1;
+
+const NONE: u32 = u32::MAX;
+const HAS_KB: u8 = 1;
+const HAS_DATE: u8 = 2;
+const HAS_HASH: u8 = 4;
+const HAS_REMOTE_ID: u8 = 8;
+
+struct Writer {
+    buf: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, v: u8) {
+        self.buf.push(v);
+    }
+
+    fn u16(&mut self, v: u16) {
+        self.buf.extend(v.to_le_bytes());
+    }
+
+    fn u32(&mut self, v: u32) {
+        self.buf.extend(v.to_le_bytes());
+    }
+
+    fn index(&mut self, v: usize) {
+        self.u32(v as u32);
+    }
+
+    fn str(&mut self, v: &str) {
+        self.index(v.len());
+        self.buf.extend(v.as_bytes());
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8]> {
+        let end = self.pos.checked_add(n).context("truncated catalog")?;
+        let bytes = self.data.get(self.pos..end).context("truncated catalog")?;
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into()?))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into()?))
+    }
+
+    fn index(&mut self, len: usize) -> Result<usize> {
+        let i = self.u32()? as usize;
+        if i >= len {
+            return Err(anyhow!("invalid node id {}", i));
+        }
+        Ok(i)
+    }
+
+    fn str(&mut self) -> Result<String> {
+        let len = self.u32()? as usize;
+        Ok(String::from_utf8(self.bytes(len)?.to_vec())?)
+    }
+}
+
+/// Serializes the catalog as is, without sorting, so that decoding yields
+/// the exact same tree ids and file order
+pub(super) fn encode(db: &Db) -> Vec<u8> {
+    let mut w = Writer { buf: MAGIC.to_vec() };
+    w.u32(VERSION);
+
+    let mut tags = db.header.tags.iter().collect::<Vec<_>>();
+    tags.sort();
+    w.index(tags.len());
+    for (k, v) in tags {
+        w.str(k);
+        w.str(v);
+    }
+
+    w.index(db.tree.arena.len());
+    for node in &db.tree.arena {
+        w.str(&node.value);
+        w.u32(node.parent.map(|p| p as u32).unwrap_or(NONE));
+        w.index(node.children.len());
+        for child in &node.children {
+            w.index(*child);
+        }
+    }
+
+    w.index(db.filemap.order.len());
+    for id in &db.filemap.order {
+        let files = &db.filemap.files[id];
+        w.index(*id);
+        w.index(files.len());
+        for file in files {
+            w.str(&file.filename);
+            let flags = file.kb.map(|_| HAS_KB).unwrap_or(0)
+                | file.date.map(|_| HAS_DATE).unwrap_or(0)
+                | file.hash.as_ref().map(|_| HAS_HASH).unwrap_or(0)
+                | file.remote_id.as_ref().map(|_| HAS_REMOTE_ID).unwrap_or(0);
+            w.u8(flags);
+            if let Some(kb) = file.kb {
+                w.u32(kb);
+            }
+            if let Some(date) = file.date {
+                w.u16(date.days());
+            }
+            if let Some(hash) = &file.hash {
+                w.str(hash);
+            }
+            if let Some(remote_id) = &file.remote_id {
+                w.str(remote_id);
+            }
+        }
+    }
+
+    w.buf
+}
+
+pub(super) fn is_binary(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+pub(super) fn decode(data: &[u8]) -> Result<Db> {
+    if !is_binary(data) {
+        return Err(anyhow!("not a binary catalog"));
+    }
+    let mut r = Reader {
+        data,
+        pos: MAGIC.len(),
+    };
+
+    let version = r.u32()?;
+    if version != VERSION && version != VERSION_1 {
+        return Err(anyhow!("unsupported catalog version {}", version));
+    }
+
+    let mut header = Header::new();
+    for _ in 0..r.u32()? {
+        let k = r.str()?;
+        let v = r.str()?;
+        header.tags.insert(k, v);
+    }
+
+    let n_nodes = r.u32()? as usize;
+    let mut arena = Vec::with_capacity(n_nodes.min(data.len()));
+    for i in 0..n_nodes {
+        let mut node = TreeNode::new(r.str()?);
+        // Parents always precede their children, which also rules out cycles
+        node.parent = match r.u32()? {
+            NONE if i == 0 => None,
+            p if (p as usize) < i => Some(p as usize),
+            p => return Err(anyhow!("invalid parent id {} for node {}", p, i)),
+        };
+        for _ in 0..r.u32()? {
+            node.children.push(r.index(n_nodes)?);
+        }
+        arena.push(node);
+    }
+    if arena.is_empty() {
+        return Err(anyhow!("catalog has no root node"));
+    }
+    let tree = Tree { arena };
+
+    let mut filemap = FileMap {
+        files: HashMap::new(),
+        order: vec![],
+    };
+    for _ in 0..r.u32()? {
+        let id = r.index(n_nodes)?;
+        let mut files = vec![];
+        for _ in 0..r.u32()? {
+            let filename = r.str()?;
+            let flags = r.u8()?;
+            let kb = if flags & HAS_KB != 0 { Some(r.u32()?) } else { None };
+            let date = if flags & HAS_DATE != 0 {
+                Some(SmallDate::from_days(r.u16()?))
+            } else {
+                None
+            };
+            let hash = if flags & HAS_HASH != 0 {
+                Some(r.str()?)
+            } else {
+                None
+            };
+            let remote_id = if flags & HAS_REMOTE_ID != 0 {
+                Some(r.str()?)
+            } else {
+                None
+            };
+            files.push(File {
+                filename,
+                kb,
+                date,
+                hash,
+                remote_id,
+            });
+        }
+        if filemap.files.insert(id, files).is_some() {
+            return Err(anyhow!("duplicate node id {}", id));
+        }
+        filemap.order.push(id);
+    }
+
+    if r.pos != data.len() {
+        return Err(anyhow!("trailing bytes in catalog"));
+    }
+
+    Ok(Db {
+        header,
+        tree,
+        filemap,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::SERIALIZED_UNSORTED;
+    use super::*;
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let mut lines = SERIALIZED_UNSORTED.lines().map(|x| x.to_owned());
+        let mut db = Db::from_lines(&mut lines)?;
+        db.add(
+            std::path::Path::new("books/fiction/no_date.txt"),
+            1,
+            SmallDate::from_days(0),
+            None,
+        )?;
+        db.filemap.files.get_mut(&5).unwrap()[0].kb = None;
+        db.filemap.files.get_mut(&2).unwrap()[0].hash = Some("d41d8cd98f00b204e9800998ecf8427e".into());
+        db.filemap.files.get_mut(&4).unwrap()[0].remote_id = Some("-100:7:BQACAgQ".into());
+
+        assert!(db
+            .to_string()
+            .contains("spaceship.txt;44;201030;;-100:7:BQACAgQ\n"));
+
+        let encoded = encode(&db);
+        let decoded = decode(&encoded)?;
+
+        assert_eq!(decoded.to_string(), db.to_string());
+        assert_eq!(encode(&decoded), encoded);
+
+        assert!(decode(&encoded[..encoded.len() - 1]).is_err());
+        assert!(decode(SERIALIZED_UNSORTED.as_bytes()).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parent_cycle() -> Result<()> {
+        let mut lines = SERIALIZED_UNSORTED.lines().map(|x| x.to_owned());
+        let mut db = Db::from_lines(&mut lines)?;
+        assert!(decode(&encode(&db)).is_ok());
+
+        // fiction as its own parent, then books and fiction as each other's parent
+        let fiction = db.tree.arena.iter().position(|n| n.value == "fiction").unwrap();
+        db.tree.arena[fiction].parent = Some(fiction);
+        assert!(decode(&encode(&db)).is_err());
+
+        let books = db.tree.arena.iter().position(|n| n.value == "books").unwrap();
+        db.tree.arena[fiction].parent = Some(books);
+        db.tree.arena[books].parent = Some(fiction);
+        assert!(decode(&encode(&db)).is_err());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,250 @@
+use super::file::File;
+use super::{Db, PASSWORD_SCHEME_TAG, SIZE_TAG, VOLUMES_TAG};
+use crate::fs::IPathBuf;
+use anyhow::Result;
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::path::PathBuf;
+
+/// Tags describing the archive a catalog belongs to, rather than its files
+const ARCHIVE_TAGS: [&str; 6] = [
+    "azure_path",
+    "hash",
+    "date",
+    SIZE_TAG,
+    VOLUMES_TAG,
+    PASSWORD_SCHEME_TAG,
+];
+
+pub enum Change {
+    Added(PathBuf, File),
+    Removed(PathBuf, File),
+    Changed(PathBuf, File, File),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MergePolicy {
+    Newest,
+    Largest,
+}
+
+fn opt_str<T: ToString>(x: Option<T>) -> String {
+    x.map(|x| x.to_string()).unwrap_or_default()
+}
+
+impl Display for Change {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let txt = match self {
+            Change::Added(path, file) => format!(
+                "+\t{}\t{}\t{}",
+                path.to_string(),
+                opt_str(file.kb),
+                opt_str(file.date)
+            ),
+            Change::Removed(path, file) => {
+                format!(
+                    "-\t{}\t{}\t{}",
+                    path.to_string(),
+                    opt_str(file.kb),
+                    opt_str(file.date)
+                )
+            }
+            Change::Changed(path, old, new) => format!(
+                "~\t{}\t{} -> {}\t{} -> {}",
+                path.to_string(),
+                opt_str(old.kb),
+                opt_str(new.kb),
+                opt_str(old.date),
+                opt_str(new.date)
+            ),
+        };
+        fmt.write_str(&txt)
+    }
+}
+
+impl MergePolicy {
+    pub fn from_str(txt: &str) -> Result<Self> {
+        match txt.to_lowercase().as_str() {
+            "newest" => Ok(Self::Newest),
+            "largest" => Ok(Self::Largest),
+            _ => Err(anyhow!(
+                "invalid merge policy {}, expected newest or largest",
+                txt
+            )),
+        }
+    }
+
+    /// Whether `other` should replace `current`, keeping `current` on ties
+    fn prefers(&self, current: &File, other: &File) -> bool {
+        let by_date = other.date.cmp(&current.date);
+        let by_kb = other.kb.cmp(&current.kb);
+        let ordering = match self {
+            Self::Newest => by_date.then(by_kb),
+            Self::Largest => by_kb.then(by_date),
+        };
+        ordering.is_gt()
+    }
+}
+
+/// Entries keyed by lowercase path, the same way filenames are deduplicated
+fn by_path(db: &Db) -> BTreeMap<String, (PathBuf, &File)> {
+    db.entries()
+        .into_iter()
+        .map(|(path, file)| (path.to_string().to_lowercase(), (path, file)))
+        .collect()
+}
+
+pub fn diff(old: &Db, new: &Db) -> Vec<Change> {
+    let old_entries = by_path(old);
+    let new_entries = by_path(new);
+    let mut changes = vec![];
+
+    for (key, (path, old_file)) in &old_entries {
+        match new_entries.get(key) {
+            None => changes.push(Change::Removed(path.clone(), (*old_file).clone())),
+            Some((new_path, new_file)) => {
+                let rehashed =
+                    old_file.hash.is_some() && new_file.hash.is_some() && old_file.hash != new_file.hash;
+                if old_file.kb != new_file.kb || old_file.date != new_file.date || rehashed {
+                    changes.push(Change::Changed(
+                        new_path.clone(),
+                        (*old_file).clone(),
+                        (*new_file).clone(),
+                    ));
+                }
+            }
+        }
+    }
+
+    for (key, (path, new_file)) in &new_entries {
+        if !old_entries.contains_key(key) {
+            changes.push(Change::Added(path.clone(), (*new_file).clone()));
+        }
+    }
+
+    changes.sort_by_key(|c| match c {
+        Change::Added(p, _) | Change::Removed(p, _) | Change::Changed(p, _, _) => {
+            p.to_string().to_lowercase()
+        }
+    });
+    changes
+}
+
+/// Catalogs of different archives can't be merged. When only one of them
+/// describes an archive, the result doesn't, as not all of its files are in it
+pub fn merge(base: &Db, other: &Db, policy: MergePolicy) -> Result<Db> {
+    let (base_archive, other_archive) = (base.tag("azure_path"), other.tag("azure_path"));
+    if let (Some(a), Some(b)) = (base_archive, other_archive) {
+        if a != b {
+            return Err(anyhow!("catalogs of different archives: {} and {}", a, b));
+        }
+    }
+    let keep_archive_tags = base_archive == other_archive;
+
+    let mut merged = Db::new();
+
+    for (k, v) in base.header.tags.iter().chain(&other.header.tags) {
+        if merged.tag(k).is_some() || (!keep_archive_tags && ARCHIVE_TAGS.contains(&k.as_str())) {
+            continue;
+        }
+        merged.add_tag(k.clone(), v.clone());
+    }
+
+    let mut entries = by_path(base);
+    for (key, (path, file)) in by_path(other) {
+        let replace = match entries.get(&key) {
+            None => true,
+            Some((_, current)) => policy.prefers(current, file),
+        };
+        if replace {
+            entries.insert(key, (path, file));
+        }
+    }
+
+    for (path, file) in entries.into_values() {
+        merged.insert(&path, file.clone())?;
+    }
+
+    Ok(merged)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::tests::SERIALIZED;
+    use super::*;
+    use crate::smalldate::SmallDate;
+    use std::path::Path;
+
+    #[test]
+    fn test_diff_and_merge() -> Result<()> {
+        let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
+        let old = Db::from_lines(&mut lines)?;
+
+        let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
+        let mut new = Db::from_lines(&mut lines)?;
+        new.add(
+            Path::new("Books/Fiction/Spaceship.txt"),
+            10,
+            SmallDate::from_str("941231")?,
+            None,
+        )?;
+        new.add(
+            Path::new("books/fiction/alien.txt"),
+            1,
+            SmallDate::from_str("220101")?,
+            None,
+        )?;
+
+        let changes = diff(&old, &new).iter().map(|c| c.to_string()).collect::<Vec<_>>();
+        assert_eq!(
+            changes,
+            vec![
+                "+\tbooks/fiction/alien.txt\t1\t220101",
+                "~\tBooks/Fiction/Spaceship.txt\t44 -> 10\t201030 -> 941231",
+            ]
+        );
+
+        let newest = merge(&old, &new, MergePolicy::Newest)?;
+        assert!(newest.to_string().contains("spaceship.txt;44;201030"));
+        assert!(newest.to_string().contains("alien.txt;1;220101"));
+        let changes = diff(&old, &newest)
+            .iter()
+            .map(|c| c.to_string())
+            .collect::<Vec<_>>();
+        assert_eq!(changes, vec!["+\tbooks/fiction/alien.txt\t1\t220101"]);
+
+        let largest = merge(&new, &old, MergePolicy::Largest)?;
+        assert!(largest.to_string().contains("spaceship.txt;44;201030"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_archives() -> Result<()> {
+        let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
+        let mut zip0 = Db::from_lines(&mut lines)?;
+        zip0.add_tag("azure_path".into(), "ByTimestamp/0.7z".into());
+        zip0.add_tag("hash".into(), "d41d8cd98f00b204e9800998ecf8427e".into());
+
+        let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
+        let mut zip1 = Db::from_lines(&mut lines)?;
+        zip1.add_tag("azure_path".into(), "ByTimestamp/1.7z".into());
+
+        assert!(merge(&zip0, &zip1, MergePolicy::Newest).is_err());
+
+        let same = merge(&zip0, &zip0, MergePolicy::Newest)?;
+        assert_eq!(
+            same.tag("azure_path").map(|s| s.as_str()),
+            Some("ByTimestamp/0.7z")
+        );
+
+        let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
+        let plain = Db::from_lines(&mut lines)?;
+        let merged = merge(&zip0, &plain, MergePolicy::Newest)?;
+        assert!(merged.tag("azure_path").is_none());
+        assert!(merged.tag("hash").is_none());
+        assert_eq!(merged.tag("foo").map(|s| s.as_str()), Some("lorem"));
+
+        Ok(())
+    }
+}
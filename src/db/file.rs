@@ -9,6 +9,10 @@ pub struct File {
     pub filename: String,
     pub kb: Option<Kb>,
     pub date: Option<SmallDate>,
+    /// md5 of the contents, absent in older catalogs
+    pub hash: Option<String>,
+    /// Where the provider keeps the file, for providers that name files by id
+    pub remote_id: Option<String>,
 }
 
 impl File {
@@ -31,19 +35,36 @@ impl File {
             Some(SmallDate::from_str(date_str)?)
         };
 
-        let file = File { filename, kb, date };
+        let hash = parts.next().filter(|h| !h.is_empty()).map(String::from);
+        let remote_id = parts.next().filter(|id| !id.is_empty()).map(String::from);
+
+        let file = File {
+            filename,
+            kb,
+            date,
+            hash,
+            remote_id,
+        };
         Ok(file)
     }
 }
 
 impl Display for File {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
-        let txt = format!(
+        let mut txt = format!(
             "{};{};{}",
             self.filename,
             self.kb.map(|x| x.to_string()).unwrap_or_else(|| "".into()),
             self.date.map(|x| x.to_string()).unwrap_or_else(|| "".into())
         );
+        if self.hash.is_some() || self.remote_id.is_some() {
+            txt.push(';');
+            txt.push_str(self.hash.as_deref().unwrap_or(""));
+        }
+        if let Some(remote_id) = &self.remote_id {
+            txt.push(';');
+            txt.push_str(remote_id);
+        }
         fmt.write_str(&txt)
     }
 }
@@ -6,8 +6,8 @@ use std::fmt::{self, Display};
 
 #[derive(Clone, Debug)]
 pub struct FileMap {
-    files: HashMap<TreeIndex, Vec<File>>,
-    order: Vec<TreeIndex>,
+    pub(super) files: HashMap<TreeIndex, Vec<File>>,
+    pub(super) order: Vec<TreeIndex>,
 }
 
 impl FileMap {
@@ -0,0 +1,315 @@
+use super::tree::Tree;
+use super::{db_paths, is_binary, read, write, SIZE_TAG, VOLUMES_TAG};
+use crate::fs::IPathBuf;
+use crate::smalldate::SmallDate;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+const REQUIRED_TAGS: [&str; 3] = ["azure_path", "hash", "date"];
+
+pub struct Problem {
+    line: Option<usize>,
+    msg: String,
+    /// Fixed by rewriting the catalog in canonical form
+    repairable: bool,
+}
+
+pub struct Report {
+    pub path: PathBuf,
+    pub problems: Vec<Problem>,
+}
+
+impl Report {
+    fn add(&mut self, line: Option<usize>, msg: String, repairable: bool) {
+        self.problems.push(Problem {
+            line,
+            msg,
+            repairable,
+        });
+    }
+
+    pub fn is_repairable(&self) -> bool {
+        !self.problems.is_empty() && self.problems.iter().all(|p| p.repairable)
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        for p in &self.problems {
+            let location = match p.line {
+                Some(l) => format!("{}:{}", self.path.to_string(), l),
+                None => self.path.to_string(),
+            };
+            let suffix = if p.repairable { " (repairable)" } else { "" };
+            fmt.write_str(&format!("{}: {}{}\n", location, p.msg, suffix))?;
+        }
+        Ok(())
+    }
+}
+
+/// Lines of a section, with their 1-based line numbers
+type Section<'a> = Vec<(usize, &'a str)>;
+
+fn sections(txt: &str) -> Vec<Section<'_>> {
+    let mut sections = vec![vec![]];
+    for (i, line) in txt.lines().enumerate() {
+        if line.starts_with('=') {
+            sections.push(vec![]);
+        } else if !line.trim().is_empty() {
+            sections.last_mut().expect("no section").push((i + 1, line));
+        }
+    }
+    sections
+}
+
+fn check_header(report: &mut Report, lines: &[(usize, &str)]) {
+    let mut tags = HashMap::new();
+
+    for (n, line) in lines {
+        match line.split_once(':') {
+            Some((k, v)) => {
+                tags.insert(k.trim(), (*n, v.trim()));
+            }
+            None => report.add(Some(*n), format!("invalid header line: {}", line), false),
+        }
+    }
+
+    for tag in REQUIRED_TAGS {
+        if !tags.contains_key(tag) {
+            report.add(None, format!("missing {} header", tag), false);
+        }
+    }
+
+    if let Some((n, date)) = tags.get("date") {
+        if SmallDate::from_str(date).is_err() {
+            report.add(Some(*n), format!("unparseable date: {}", date), false);
+        }
+    }
+
+    if let Some((n, size)) = tags.get(SIZE_TAG) {
+        if size.parse::<u64>().is_err() {
+            report.add(Some(*n), format!("invalid size: {}", size), false);
+        }
+    }
+
+    if let Some((n, volumes)) = tags.get(VOLUMES_TAG) {
+        if volumes.parse::<usize>().map(|v| v < 2).unwrap_or(true) {
+            report.add(Some(*n), format!("invalid volumes: {}", volumes), false);
+        }
+    }
+}
+
+/// Returns the number of tree nodes, root included
+fn check_tree(report: &mut Report, lines: &[(usize, &str)]) -> usize {
+    let mut depth = 0;
+
+    for (n, line) in lines {
+        let indentation = &line[..line.len() - line.trim_start().len()];
+        if indentation.contains('\t') {
+            report.add(Some(*n), "indentation contains tabs".into(), false);
+            continue;
+        }
+
+        let spaces = indentation.len();
+        if spaces % 2 != 0 {
+            report.add(
+                Some(*n),
+                format!("indentation of {} spaces is not a multiple of two", spaces),
+                true,
+            );
+        }
+
+        let new_depth = spaces / 2 + 1;
+        if new_depth > depth + 1 {
+            report.add(
+                Some(*n),
+                format!("indentation jumps from depth {} to {}", depth, new_depth),
+                false,
+            );
+        }
+        depth = new_depth;
+    }
+
+    let mut owned = lines.iter().map(|(_, l)| l.to_string());
+    Tree::from_lines(&mut owned).map(|t| t.arena.len()).unwrap_or(1)
+}
+
+fn check_files(report: &mut Report, lines: &[(usize, &str)], n_nodes: usize) {
+    let mut id = 0;
+    // lowercase filename to line number, per id
+    let mut seen: HashMap<usize, HashMap<String, usize>> = HashMap::new();
+
+    for (n, line) in lines {
+        if !line.contains(';') {
+            match line.trim().parse::<usize>() {
+                Ok(i) if i < n_nodes => id = i,
+                Ok(i) => {
+                    report.add(Some(*n), format!("orphaned id {} not found in tree", i), false);
+                    id = i;
+                }
+                Err(_) => report.add(Some(*n), format!("invalid id: {}", line), false),
+            }
+            continue;
+        }
+
+        let parts = line.split(';').collect::<Vec<_>>();
+        if parts.len() < 3 {
+            report.add(Some(*n), format!("expected filename;kb;date: {}", line), false);
+            continue;
+        }
+        let (filename, kb, date) = (parts[0], parts[1], parts[2]);
+
+        if !kb.is_empty() && kb.parse::<u32>().is_err() {
+            report.add(Some(*n), format!("invalid kb: {}", kb), false);
+        }
+        if !date.is_empty() && SmallDate::from_str(date).is_err() {
+            report.add(Some(*n), format!("unparseable date: {}", date), false);
+        }
+        if let Some(hash) = parts.get(3).filter(|h| !h.is_empty()) {
+            if hash.len() != 32 || !hash.chars().all(|c| c.is_ascii_hexdigit()) {
+                report.add(Some(*n), format!("invalid hash: {}", hash), false);
+            }
+        }
+
+        let previous = seen.entry(id).or_default().insert(filename.to_lowercase(), *n);
+        if let Some(p) = previous {
+            report.add(
+                Some(*n),
+                format!("duplicate filename {} (also on line {})", filename, p),
+                true,
+            );
+        }
+    }
+}
+
+fn check_txt(path: &Path) -> Result<Report> {
+    let mut report = Report {
+        path: path.into(),
+        problems: vec![],
+    };
+
+    let txt =
+        std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.to_string()))?;
+    let sections = sections(&txt);
+    if sections.len() != 3 {
+        report.add(
+            None,
+            format!("expected 3 sections separated by =, found {}", sections.len()),
+            false,
+        );
+        return Ok(report);
+    }
+
+    check_header(&mut report, &sections[0]);
+    let n_nodes = check_tree(&mut report, &sections[1]);
+    check_files(&mut report, &sections[2], n_nodes);
+
+    if report.problems.iter().all(|p| p.repairable) {
+        let canonical = read(path)?.to_string();
+        if canonical.trim() != txt.trim() {
+            report.add(None, "not in canonical sorted form".into(), true);
+        }
+    }
+
+    Ok(report)
+}
+
+fn check_binary(path: &Path) -> Report {
+    let mut report = Report {
+        path: path.into(),
+        problems: vec![],
+    };
+
+    match read(path) {
+        Ok(db) => {
+            for tag in REQUIRED_TAGS {
+                if db.tag(tag).is_none() {
+                    report.add(None, format!("missing {} header", tag), false);
+                }
+            }
+        }
+        Err(e) => report.add(None, e.to_string(), false),
+    }
+
+    report
+}
+
+pub fn check(path: &Path) -> Result<Report> {
+    if is_binary(path) {
+        Ok(check_binary(path))
+    } else {
+        check_txt(path)
+    }
+}
+
+/// Checks every catalog under the folder, optionally rewriting the repairable ones
+pub fn run(db_folder: &Path, fix: bool) -> Result<Vec<Report>> {
+    let mut paths = db_paths(db_folder).collect::<Vec<_>>();
+    paths.sort();
+
+    let mut reports = vec![];
+    for path in paths {
+        let report = check(&path)?;
+        if fix && report.is_repairable() {
+            write(read(&path)?, &path)?;
+        }
+        reports.push(report);
+    }
+
+    Ok(reports)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(txt: &str) -> Result<Vec<String>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("a.storm.txt");
+        std::fs::write(&path, txt)?;
+        let report = check(&path)?;
+        Ok(report
+            .problems
+            .iter()
+            .map(|p| format!("{:?}: {}", p.line, p.msg))
+            .collect())
+    }
+
+    #[test]
+    fn test_check() -> Result<()> {
+        let txt = "azure_path: a.7z
+hash: abc
+date: 220101
+=
+books
+   fiction
+      horror
+=
+3
+spaceship.txt;44;201030
+Spaceship.txt;44;201030
+9
+monster.txt;22;931306";
+
+        assert_eq!(
+            messages(txt)?,
+            vec![
+                "Some(6): indentation of 3 spaces is not a multiple of two",
+                "Some(7): indentation jumps from depth 2 to 4",
+                "Some(11): duplicate filename Spaceship.txt (also on line 10)",
+                "Some(12): orphaned id 9 not found in tree",
+                "Some(13): unparseable date: 931306",
+            ]
+        );
+
+        let txt = "hash: abc\ndate: 220101\n=\nbooks\n=\n1\nb.txt;1;201030\na.txt;1;201030";
+        assert_eq!(messages(txt)?, vec!["None: missing azure_path header"]);
+
+        let txt = format!("azure_path: a.7z\n{}", txt);
+        assert_eq!(messages(&txt)?, vec!["None: not in canonical sorted form"]);
+
+        Ok(())
+    }
+}
@@ -0,0 +1,280 @@
+use super::{db_paths, read};
+use crate::fs::{self, IPathBuf};
+use crate::plan;
+use anyhow::{Context, Result};
+use memmap2::Mmap;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+const MAGIC: &[u8; 8] = b"STORMIDX";
+const VERSION: u32 = 2;
+// magic, version, padding, signature, path count and content count
+const HEADER_LEN: usize = 40;
+
+pub const INDEX_FILENAME: &str = "hashes.idx";
+
+/// Path hashes of every catalog in a folder as (path, content) pairs sorted
+/// by path, followed by the sorted known content hashes, as written to disk
+struct HashIndex {
+    mmap: Mmap,
+    n_paths: usize,
+    n_contents: usize,
+}
+
+impl HashIndex {
+    fn open(path: &Path) -> Result<Self> {
+        let file = fs::open(path)?;
+        // the index is only ever replaced, never modified in place
+        let mmap = unsafe { Mmap::map(&file)? };
+
+        if mmap.len() < HEADER_LEN || &mmap[0..8] != MAGIC {
+            return Err(anyhow!("invalid hash index"));
+        }
+        let version = u32::from_le_bytes(mmap[8..12].try_into()?);
+        if version != VERSION {
+            return Err(anyhow!("unsupported hash index version {}", version));
+        }
+        let n_paths = u64::from_le_bytes(mmap[24..32].try_into()?) as usize;
+        let n_contents = u64::from_le_bytes(mmap[32..40].try_into()?) as usize;
+        if mmap.len() != HEADER_LEN + n_paths * 16 + n_contents * 8 {
+            return Err(anyhow!("truncated hash index"));
+        }
+
+        Ok(Self {
+            mmap,
+            n_paths,
+            n_contents,
+        })
+    }
+
+    fn u64_at(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.mmap[offset..offset + 8].try_into().expect("invalid entry"))
+    }
+
+    fn signature(&self) -> u64 {
+        self.u64_at(16)
+    }
+
+    fn path(&self, i: usize) -> (u64, u64) {
+        let offset = HEADER_LEN + i * 16;
+        (self.u64_at(offset), self.u64_at(offset + 8))
+    }
+
+    fn content(&self, i: usize) -> u64 {
+        self.u64_at(HEADER_LEN + self.n_paths * 16 + i * 8)
+    }
+
+    /// Contents of every entry with the given path hash
+    fn path_contents(&self, needle: u64) -> Vec<u64> {
+        let first = partition_point(self.n_paths, |i| self.path(i).0 < needle);
+        (first..self.n_paths)
+            .map(|i| self.path(i))
+            .take_while(|(path, _)| *path == needle)
+            .map(|(_, content)| content)
+            .collect()
+    }
+
+    fn has_content(&self, needle: u64) -> bool {
+        let i = partition_point(self.n_contents, |i| self.content(i) < needle);
+        i < self.n_contents && self.content(i) == needle
+    }
+}
+
+/// First index in 0..len for which `is_less` is false
+fn partition_point(len: usize, is_less: impl Fn(usize) -> bool) -> usize {
+    let (mut lo, mut hi) = (0, len);
+    while lo < hi {
+        let mid = lo + (hi - lo) / 2;
+        if is_less(mid) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    lo
+}
+
+fn contents(paths: &[(u64, u64)]) -> Vec<u64> {
+    let mut contents = paths
+        .iter()
+        .map(|(_, c)| *c)
+        .filter(|c| *c != 0)
+        .collect::<Vec<_>>();
+    contents.sort_unstable();
+    contents.dedup();
+    contents
+}
+
+fn encode(signature: u64, paths: &[(u64, u64)]) -> Vec<u8> {
+    let contents = contents(paths);
+
+    let mut buf = Vec::with_capacity(HEADER_LEN + paths.len() * 16 + contents.len() * 8);
+    buf.extend(MAGIC);
+    buf.extend(VERSION.to_le_bytes());
+    buf.extend(0u32.to_le_bytes());
+    buf.extend(signature.to_le_bytes());
+    buf.extend((paths.len() as u64).to_le_bytes());
+    buf.extend((contents.len() as u64).to_le_bytes());
+    for (path, content) in paths {
+        buf.extend(path.to_le_bytes());
+        buf.extend(content.to_le_bytes());
+    }
+    for content in contents {
+        buf.extend(content.to_le_bytes());
+    }
+    buf
+}
+
+/// Changes whenever a catalog is added, removed or rewritten
+fn signature(db_folder: &Path) -> Result<u64> {
+    let mut paths = db_paths(db_folder).collect::<Vec<_>>();
+    paths.sort();
+
+    let mut txt = String::new();
+    for path in paths {
+        let metadata = std::fs::metadata(&path)?;
+        let modified = metadata.modified()?.duration_since(UNIX_EPOCH)?.as_nanos();
+        txt.push_str(&format!("{};{};{}\n", path.to_string(), metadata.len(), modified));
+    }
+
+    let digest = md5::compute(txt);
+    Ok(u64::from_le_bytes(digest[0..8].try_into()?))
+}
+
+fn collect(db_folder: &Path) -> Result<Vec<(u64, u64)>> {
+    let mut hashes = vec![];
+
+    for path in db_paths(db_folder) {
+        let db = read(&path).with_context(|| format!("Invalid db {}", path.to_string()))?;
+        hashes.append(&mut db.hashes());
+    }
+
+    hashes.sort_unstable();
+    hashes.dedup();
+    Ok(hashes)
+}
+
+/// Archived path and content hashes, backed by the on-disk index when it is
+/// up to date, plus whatever has been archived since it was loaded
+pub struct Hashes {
+    index: Option<HashIndex>,
+    paths: Vec<(u64, u64)>,
+    contents: Vec<u64>,
+}
+
+impl Hashes {
+    pub(super) fn new(mut paths: Vec<(u64, u64)>) -> Self {
+        paths.sort_unstable();
+        paths.dedup();
+        let contents = contents(&paths);
+        Self {
+            index: None,
+            paths,
+            contents,
+        }
+    }
+
+    /// A content match is enough, otherwise the path must match with either
+    /// the same or an unknown content
+    pub(super) fn is_archived(&self, path: u64, content: u64) -> bool {
+        if content != 0 {
+            let in_index = self
+                .index
+                .as_ref()
+                .map(|i| i.has_content(content))
+                .unwrap_or(false);
+            if in_index || self.contents.binary_search(&content).is_ok() {
+                return true;
+            }
+        }
+
+        let mut path_contents = self
+            .index
+            .as_ref()
+            .map(|i| i.path_contents(path))
+            .unwrap_or_default();
+        let first = self.paths.partition_point(|(p, _)| *p < path);
+        path_contents.extend(
+            self.paths[first..]
+                .iter()
+                .take_while(|(p, _)| *p == path)
+                .map(|(_, c)| *c),
+        );
+
+        path_contents
+            .iter()
+            .any(|c| content == 0 || *c == 0 || *c == content)
+    }
+
+    pub(super) fn insert(&mut self, path: u64, content: u64) {
+        if let Err(i) = self.paths.binary_search(&(path, content)) {
+            self.paths.insert(i, (path, content));
+        }
+        if content != 0 {
+            if let Err(i) = self.contents.binary_search(&content) {
+                self.contents.insert(i, content);
+            }
+        }
+    }
+}
+
+/// Rebuilds the index only if the catalogs changed since it was written
+pub(super) fn load(db_folder: &Path) -> Result<Hashes> {
+    let index_path = db_folder.join(INDEX_FILENAME);
+    let signature = signature(db_folder)?;
+
+    if let Ok(index) = HashIndex::open(&index_path) {
+        if index.signature() == signature {
+            let mut hashes = Hashes::new(vec![]);
+            hashes.index = Some(index);
+            return Ok(hashes);
+        }
+    }
+
+    let paths = collect(db_folder)?;
+    if plan::is_dry_run() {
+        return Ok(Hashes::new(paths));
+    }
+
+    let tmp_path = db_folder.join(format!("{}.tmp", INDEX_FILENAME));
+    fs::write(&tmp_path, encode(signature, &paths))?;
+    fs::replace(&tmp_path, &index_path)?;
+
+    let mut hashes = Hashes::new(vec![]);
+    hashes.index = Some(HashIndex::open(&index_path)?);
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::db::hash;
+    use crate::db::tests::SERIALIZED;
+
+    #[test]
+    fn test_load() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        std::fs::write(dir.path().join("a.storm.txt"), SERIALIZED)?;
+
+        let hashes = load(dir.path())?;
+        assert!(hashes.index.is_some());
+        assert!(hashes.is_archived(hash("books/fiction/spaceship.txt"), 0));
+        assert!(!hashes.is_archived(hash("books/fiction/alien.txt"), 0));
+
+        let index = HashIndex::open(&dir.path().join(INDEX_FILENAME))?;
+        assert_eq!(index.n_paths, 4);
+        assert_eq!(index.n_contents, 0);
+        assert_eq!(index.signature(), signature(dir.path())?);
+
+        std::fs::write(
+            dir.path().join("b.storm.txt"),
+            "=\nbooks\n=\n1\nalien.txt;1;201030;0123456789abcdef0123456789abcdef",
+        )?;
+        let hashes = load(dir.path())?;
+        assert!(hashes.is_archived(hash("books/alien.txt"), 0));
+        assert!(hashes.is_archived(hash("renamed.txt"), 0x0123456789abcdef));
+        assert!(!hashes.is_archived(hash("books/alien.txt"), 1));
+
+        Ok(())
+    }
+}
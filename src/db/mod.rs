@@ -1,6 +1,11 @@
+mod binary;
+pub mod diff;
 pub mod file;
 mod filemap;
+pub mod fsck;
 mod header;
+mod index;
+pub mod query;
 mod tree;
 
 use self::file::Kb;
@@ -8,16 +13,25 @@ use self::header::Header;
 use self::tree::TreeIndex;
 use crate::fs::{self, IPathBuf};
 use crate::smalldate::SmallDate;
-use anyhow::{Context, Result};
+use crate::zip;
+use anyhow::{anyhow, Context, Result};
 use file::File;
 use filemap::FileMap;
-use std::collections::hash_map::DefaultHasher;
+pub use index::Hashes;
 use std::fmt::{self, Display};
-use std::hash::{Hash, Hasher};
 use std::path::{Path, PathBuf};
 use tree::Tree;
 use walkdir::{DirEntry, WalkDir};
 
+pub const PASSWORD_SCHEME_TAG: &str = "password_scheme";
+/// Size in bytes of the archive a catalog describes
+pub const SIZE_TAG: &str = "size";
+/// Number of volumes an archive was split into, absent when it wasn't
+pub const VOLUMES_TAG: &str = "volumes";
+
+const TXT_EXTENSION: &str = ".storm.txt";
+const BIN_EXTENSION: &str = ".storm.bin";
+
 pub struct Db {
     header: Header,
     tree: Tree,
@@ -50,25 +64,34 @@ impl Db {
         self.header.tags.insert(k, v);
     }
 
+    pub fn remove_tag(&mut self, k: &str) {
+        self.header.tags.remove(k);
+    }
+
+    pub fn tag(&self, k: &str) -> Option<&String> {
+        self.header.tags.get(k)
+    }
+
     pub fn add_file(&mut self, filepath: &Path, prefix_to_strip: &Path) -> Result<()> {
-        let (kb, date) = fs::metadata(filepath)?;
-        let relative_filepath = filepath.strip_prefix(prefix_to_strip)?;
-        self.add(relative_filepath, kb, date)
+        let (path, file) = describe(filepath, prefix_to_strip, None)?;
+        self.insert(&path, file)
     }
 
-    pub fn add(&mut self, path: &Path, kb: Kb, date: SmallDate) -> Result<()> {
-        let parent = path.parent().context("Invalid parent")?;
-        let filename = path
-            .file_name()
-            .context("Invalid filename")?
-            .to_string_lossy()
-            .to_string();
-        let parent_id = self.tree.add_path(parent)?;
+    pub fn add(&mut self, path: &Path, kb: Kb, date: SmallDate, hash: Option<String>) -> Result<()> {
         let file = File {
-            filename,
+            filename: filename(path)?,
             kb: Some(kb),
             date: Some(date),
+            hash,
+            remote_id: None,
         };
+        self.insert(path, file)
+    }
+
+    /// Adds the file under the parent of `path`, whose filename is ignored
+    pub fn insert(&mut self, path: &Path, file: File) -> Result<()> {
+        let parent = path.parent().context("Invalid parent")?;
+        let parent_id = self.tree.add_path(parent)?;
         self.filemap.insert(&parent_id, file);
         Ok(())
     }
@@ -89,7 +112,15 @@ impl Db {
                 .to_string();
             let kb = None;
             let date = None;
-            let file = File { filename, kb, date };
+            let hash = None;
+            let remote_id = None;
+            let file = File {
+                filename,
+                kb,
+                date,
+                hash,
+                remote_id,
+            };
             filemap.insert(&index, file);
         }
         Ok(Self {
@@ -103,6 +134,9 @@ impl Db {
         let header = Header::from_lines(lines)?;
         let tree = Tree::from_lines(lines)?;
         let filemap = FileMap::from_lines(lines)?;
+        if let Some(id) = filemap.order.iter().find(|id| tree.node(**id).is_err()) {
+            return Err(anyhow!("orphaned id {} not found in tree", id));
+        }
         Ok(Self {
             header,
             tree,
@@ -126,18 +160,30 @@ impl Db {
         (path, file)
     }
 
+    pub fn entries(&self) -> Vec<(PathBuf, &File)> {
+        self.iter()
+            .map(|entry| {
+                let (mut path, file) = self.entry_pair(&entry);
+                path.push(&file.filename);
+                (path, file)
+            })
+            .collect()
+    }
+
     fn sort_value(&self, entry: &DbEntry) -> String {
         let (mut path, file) = self.entry_pair(entry);
         path.push(&file.filename);
         path.to_string().to_lowercase()
     }
 
-    fn hashes(&self) -> Vec<u64> {
+    /// Path and content hashes, the latter being 0 when unknown
+    fn hashes(&self) -> Vec<(u64, u64)> {
         self.iter()
             .map(|entry| {
                 let (mut path, file) = self.entry_pair(&entry);
                 path.push(&file.filename);
-                hash(&path.to_string())
+                let content = file.hash.as_deref().map(content_hash).unwrap_or(0);
+                (hash(&path.to_string()), content)
             })
             .collect()
     }
@@ -233,18 +279,62 @@ impl<'a> Iterator for DbIter<'a> {
     }
 }
 
+// stable across builds, since hashes are persisted in the index
 fn hash(txt: &str) -> u64 {
-    let mut hasher = DefaultHasher::new();
+    let txt = txt.trim_start_matches('/');
+    // volumes of a split archive stand for the archive itself
+    let txt = match zip::volume_of(Path::new(txt)) {
+        Some((archive, _)) => archive.to_string(),
+        None => txt.to_owned(),
+    };
+    let normalized = txt.trim_end_matches(".7z").to_lowercase();
+    let digest = md5::compute(normalized);
+    u64::from_le_bytes(digest[0..8].try_into().expect("invalid digest"))
+}
+
+/// First 8 bytes of a hex md5 digest
+fn content_hash(md5: &str) -> u64 {
+    md5.get(0..16)
+        .and_then(|prefix| u64::from_str_radix(prefix, 16).ok())
+        .unwrap_or(0)
+}
 
-    txt.trim_start_matches('/')
-        .trim_end_matches(".7z")
-        .to_lowercase()
-        .hash(&mut hasher);
+fn filename(path: &Path) -> Result<String> {
+    Ok(path
+        .file_name()
+        .context("Invalid filename")?
+        .to_string_lossy()
+        .to_string())
+}
 
-    hasher.finish()
+fn is_binary(path: &Path) -> bool {
+    path.to_string().ends_with(BIN_EXTENSION)
+}
+
+/// The catalog path and entry of a local file, with the id the provider gave it
+pub fn describe(
+    filepath: &Path,
+    prefix_to_strip: &Path,
+    remote_id: Option<String>,
+) -> Result<(PathBuf, File)> {
+    let (kb, date) = fs::metadata(filepath)?;
+    let relative_filepath = filepath.strip_prefix(prefix_to_strip)?;
+    let file = File {
+        filename: filename(relative_filepath)?,
+        kb: Some(kb),
+        date: Some(date),
+        hash: Some(fs::md5(filepath)?),
+        remote_id,
+    };
+    Ok((relative_filepath.to_owned(), file))
 }
 
 pub fn read(path: &Path) -> Result<Db> {
+    if is_binary(path) {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {}", path.to_string()))?;
+        return binary::decode(&data);
+    }
+
     let mut lines = fs::read_lines(path)?;
     Db::from_lines(&mut lines)
 }
@@ -255,16 +345,27 @@ pub fn read_list(path: &Path) -> Result<Db> {
 }
 
 pub fn write(db: Db, path: &Path) -> Result<()> {
+    if is_binary(path) {
+        fs::write(path, binary::encode(&db.sort()?))?;
+        return Ok(());
+    }
+
     let serialized = db.to_string();
     fs::write(path, &serialized)?;
     Ok(())
 }
 
+/// Converts between the text and binary formats, depending on the extensions
+pub fn convert(from: &Path, to: &Path) -> Result<()> {
+    let db = read(from).with_context(|| format!("Invalid db {}", from.to_string()))?;
+    write(db, to)
+}
+
 fn is_db(entry: &DirEntry) -> bool {
     entry
         .file_name()
         .to_str()
-        .map(|s| s.ends_with(".storm.txt"))
+        .map(|s| s.ends_with(TXT_EXTENSION) || s.ends_with(BIN_EXTENSION))
         .unwrap_or(false)
 }
 
@@ -285,26 +386,36 @@ fn is_db(entry: &DirEntry) -> bool {
 //     None
 // }
 
-pub fn all_hashes(db_folder: &Path) -> Vec<u64> {
-    let mut hashes = vec![];
+fn db_paths(db_folder: &Path) -> impl Iterator<Item = PathBuf> {
+    WalkDir::new(db_folder)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(is_db)
+        .map(|e| e.path().to_owned())
+}
 
-    let walker = WalkDir::new(db_folder).into_iter();
-    for entry in walker.filter_map(|e| e.ok()) {
-        if !is_db(&entry) {
-            continue;
-        }
-        let mut lines = fs::read_lines(entry.path()).expect("invalid file");
-        let db = Db::from_lines(&mut lines).expect("Invalid db");
-        hashes.append(&mut db.hashes());
-    }
+pub fn read_all(db_folder: &Path) -> Result<Vec<(PathBuf, Db)>> {
+    db_paths(db_folder)
+        .map(|p| {
+            let db = read(&p).with_context(|| format!("Invalid db {}", p.to_string()))?;
+            Ok((p, db))
+        })
+        .collect()
+}
 
-    hashes.sort_unstable();
-    hashes
+pub fn all_hashes(db_folder: &Path) -> Result<Hashes> {
+    index::load(db_folder)
 }
 
-pub fn has(path: &Path, hashes: &[u64]) -> bool {
-    let needle = hash(&path.to_string());
-    hashes.binary_search(&needle).is_ok()
+pub fn insert_hash(hashes: &mut Hashes, path: &Path, md5: Option<&str>) {
+    hashes.insert(hash(&path.to_string()), md5.map(content_hash).unwrap_or(0));
+}
+
+/// Whether the file was archived, either under the same path or, when its
+/// md5 is known, with the same contents. A known md5 that differs from the
+/// archived one means it is a new version of the file.
+pub fn has(path: &Path, md5: Option<&str>, hashes: &Hashes) -> bool {
+    hashes.is_archived(hash(&path.to_string()), md5.map(content_hash).unwrap_or(0))
 }
 
 #[cfg(test)]
@@ -313,7 +424,7 @@ mod tests {
 
     use super::*;
 
-    const SERIALIZED_UNSORTED: &str = "foo: lorem
+    pub(super) const SERIALIZED_UNSORTED: &str = "foo: lorem
 bar: ipsum
 =
 dcim
@@ -330,7 +441,7 @@ monster.txt;22;930606
 4
 spaceship.txt;44;201030";
 
-    const SERIALIZED: &str = "bar: ipsum
+    pub(super) const SERIALIZED: &str = "bar: ipsum
 foo: lorem
 =
 books
@@ -360,6 +471,13 @@ mountain.jpg;456;211230";
         Ok(())
     }
 
+    #[test]
+    fn test_orphaned_id() {
+        let serialized = SERIALIZED.replace("\n5\n", "\n9\n");
+        let mut lines = serialized.lines().map(|x| x.to_owned());
+        assert!(Db::from_lines(&mut lines).is_err());
+    }
+
     #[test]
     fn test_deser() -> Result<()> {
         let mut lines = SERIALIZED_UNSORTED.lines().map(|x| x.to_owned());
@@ -383,7 +501,7 @@ mountain.jpg;456;211230";
         let path = PathBuf::from("books/fiction/spaceship.txt");
         let kb = 123;
         let date = SmallDate::from_str("941231")?;
-        db.add(&path, kb, date)?;
+        db.add(&path, kb, date, None)?;
 
         let expected = SERIALIZED
             .trim()
@@ -413,14 +531,48 @@ mountain.jpg;456;211230";
         let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
         let db = Db::from_lines(&mut lines)?;
 
-        let mut hashes = db.hashes();
-        hashes.sort_unstable();
+        let hashes = Hashes::new(db.hashes());
 
         let path = PathBuf::from("books/fiction/alien.txt");
-        assert!(!has(&path, &hashes));
+        assert!(!has(&path, None, &hashes));
 
         let path = PathBuf::from("books/fiction/spaceship.txt");
-        assert!(has(&path, &hashes));
+        assert!(has(&path, None, &hashes));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_content_hashes() -> Result<()> {
+        let mut lines = SERIALIZED.lines().map(|x| x.to_owned());
+        let mut db = Db::from_lines(&mut lines)?;
+        let md5 = "d41d8cd98f00b204e9800998ecf8427e";
+        let other_md5 = "0cc175b9c0f1b6a831c399e269772661";
+        db.insert(
+            Path::new("books/fiction/alien.txt"),
+            File::from_line(&format!("alien.txt;1;201030;{}", md5))?,
+        )?;
+        assert!(db.to_string().contains(&format!("alien.txt;1;201030;{}\n", md5)));
+
+        let hashes = Hashes::new(db.hashes());
+
+        // renamed
+        assert!(has(Path::new("books/alien.txt"), Some(md5), &hashes));
+        // new version
+        assert!(!has(
+            Path::new("books/fiction/alien.txt"),
+            Some(other_md5),
+            &hashes
+        ));
+        // no md5 for the archived one, so it can't be told apart
+        assert!(has(
+            Path::new("books/fiction/spaceship.txt"),
+            Some(other_md5),
+            &hashes
+        ));
+        // volumes of an encrypted source
+        assert!(has(Path::new("books/fiction/alien.txt.7z.002"), None, &hashes));
+        assert!(!has(Path::new("books/fiction/other.txt.7z.001"), None, &hashes));
 
         Ok(())
     }
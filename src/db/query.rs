@@ -0,0 +1,254 @@
+use super::file::{File, Kb};
+use super::Db;
+use crate::fs::IPathBuf;
+use crate::smalldate::SmallDate;
+use anyhow::{Context, Result};
+use regex::{Regex, RegexBuilder};
+use std::fmt::{self, Display};
+use std::path::{Path, PathBuf};
+
+#[derive(Default)]
+pub struct Query {
+    path: Option<Regex>,
+    from: Option<SmallDate>,
+    to: Option<SmallDate>,
+    min_kb: Option<Kb>,
+    max_kb: Option<Kb>,
+}
+
+pub struct Match {
+    pub path: PathBuf,
+    pub file: File,
+    pub azure_path: Option<String>,
+    pub hash: Option<String>,
+    pub date: Option<String>,
+    pub scheme: Option<String>,
+    pub volumes: Option<String>,
+}
+
+impl Query {
+    pub fn new(
+        glob: Option<&str>,
+        regex: Option<&str>,
+        from: Option<&str>,
+        to: Option<&str>,
+        min_kb: Option<Kb>,
+        max_kb: Option<Kb>,
+    ) -> Result<Self> {
+        let pattern = match (glob, regex) {
+            (Some(_), Some(_)) => return Err(anyhow!("glob and regex are mutually exclusive")),
+            (Some(g), None) => Some(glob_to_regex(g)),
+            (None, Some(r)) => Some(r.to_owned()),
+            (None, None) => None,
+        };
+
+        let path = match pattern {
+            Some(p) => Some(
+                RegexBuilder::new(&p)
+                    .case_insensitive(true)
+                    .build()
+                    .with_context(|| format!("invalid pattern: {}", p))?,
+            ),
+            None => None,
+        };
+
+        let from = from.map(SmallDate::from_str).transpose()?;
+        let to = to.map(SmallDate::from_str).transpose()?;
+
+        Ok(Self {
+            path,
+            from,
+            to,
+            min_kb,
+            max_kb,
+        })
+    }
+
+    pub fn matches(&self, path: &Path, file: &File) -> bool {
+        if let Some(regex) = &self.path {
+            let path_str = path.to_string();
+            let path_str = path_str.trim_start_matches('/');
+            if !regex.is_match(path_str) {
+                return false;
+            }
+        }
+
+        if self.from.is_some() || self.to.is_some() {
+            match file.date {
+                None => return false,
+                Some(d) => {
+                    if self.from.map(|from| d < from).unwrap_or(false) {
+                        return false;
+                    }
+                    if self.to.map(|to| d > to).unwrap_or(false) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        if self.min_kb.is_some() || self.max_kb.is_some() {
+            match file.kb {
+                None => return false,
+                Some(kb) => {
+                    if self.min_kb.map(|min| kb < min).unwrap_or(false) {
+                        return false;
+                    }
+                    if self.max_kb.map(|max| kb > max).unwrap_or(false) {
+                        return false;
+                    }
+                }
+            }
+        }
+
+        true
+    }
+
+    fn search(&self, db: &Db) -> Vec<Match> {
+        let tag = |k: &str| db.tag(k).cloned();
+
+        db.entries()
+            .into_iter()
+            .filter(|(path, file)| self.matches(path, file))
+            .map(|(path, file)| Match {
+                path,
+                file: file.clone(),
+                azure_path: tag("azure_path"),
+                hash: tag("hash"),
+                date: tag("date"),
+                scheme: tag(super::PASSWORD_SCHEME_TAG),
+                volumes: tag(super::VOLUMES_TAG),
+            })
+            .collect()
+    }
+}
+
+impl Display for Match {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        let txt = format!(
+            "{}\t{}\t{}\t{}\t{}\t{}",
+            self.path.to_string(),
+            self.file.kb.map(|x| x.to_string()).unwrap_or_else(|| "-".into()),
+            self.file
+                .date
+                .map(|x| x.to_string())
+                .unwrap_or_else(|| "-".into()),
+            self.azure_path.as_deref().unwrap_or("-"),
+            self.hash.as_deref().unwrap_or("-"),
+            self.date.as_deref().unwrap_or("-")
+        );
+        fmt.write_str(&txt)
+    }
+}
+
+fn glob_to_regex(glob: &str) -> String {
+    let mut re = String::from("^");
+    let mut chars = glob.trim_start_matches('/').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '*' => {
+                if chars.peek() == Some(&'*') {
+                    chars.next();
+                    if chars.peek() == Some(&'/') {
+                        chars.next();
+                        re.push_str("(?:.*/)?");
+                    } else {
+                        re.push_str(".*");
+                    }
+                } else {
+                    re.push_str("[^/]*");
+                }
+            }
+            '?' => re.push_str("[^/]"),
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+    }
+
+    re.push('$');
+    re
+}
+
+pub fn run(db_folder: &Path, query: &Query) -> Result<Vec<Match>> {
+    let mut matches = vec![];
+
+    for (_, db) in super::read_all(db_folder)? {
+        matches.append(&mut query.search(&db));
+    }
+
+    Ok(matches)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(kb: Kb, date: &str) -> File {
+        File {
+            filename: "".into(),
+            kb: Some(kb),
+            date: Some(SmallDate::from_str(date).unwrap()),
+            hash: None,
+            remote_id: None,
+        }
+    }
+
+    #[test]
+    fn test_glob() -> Result<()> {
+        let cases = [
+            ("dcim/camera/*.jpg", "dcim/camera/beach.jpg", true),
+            ("dcim/*.jpg", "dcim/camera/beach.jpg", false),
+            ("dcim/**/*.jpg", "dcim/camera/beach.jpg", true),
+            ("**/beach.jpg", "beach.jpg", true),
+            ("dcim/camera/beach.jp?", "dcim/camera/beach.jpg", true),
+            ("DCIM/Camera/*", "dcim/camera/beach.jpg", true),
+            ("dcim/camera/(1).jpg", "dcim/camera/(1).jpg", true),
+        ];
+
+        for (glob, path, expected) in cases {
+            let query = Query::new(Some(glob), None, None, None, None, None)?;
+            assert_eq!(
+                query.matches(&PathBuf::from(path), &file(1, "210101")),
+                expected,
+                "{}",
+                glob
+            );
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_regex() -> Result<()> {
+        let query = Query::new(None, Some(r"^DCIM/\D+/\d+\.jpg$"), None, None, None, None)?;
+        assert!(query.matches(Path::new("dcim/Camera/01.jpg"), &file(1, "210101")));
+        assert!(!query.matches(Path::new("dcim/2021/01.jpg"), &file(1, "210101")));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_ranges() -> Result<()> {
+        let query = Query::new(None, None, Some("210101"), Some("211231"), Some(10), Some(100))?;
+        let path = PathBuf::from("foo.txt");
+
+        assert!(query.matches(&path, &file(10, "210101")));
+        assert!(query.matches(&path, &file(100, "211231")));
+        assert!(!query.matches(&path, &file(9, "210601")));
+        assert!(!query.matches(&path, &file(101, "210601")));
+        assert!(!query.matches(&path, &file(50, "201231")));
+        assert!(!query.matches(&path, &file(50, "220101")));
+
+        let undated = File {
+            filename: "".into(),
+            kb: None,
+            date: None,
+            hash: None,
+            remote_id: None,
+        };
+        assert!(!query.matches(&path, &undated));
+        assert!(Query::default().matches(&path, &undated));
+
+        Ok(())
+    }
+}
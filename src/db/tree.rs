@@ -8,13 +8,13 @@ pub type TreeIndex = usize;
 
 #[derive(Debug, Clone)]
 pub(super) struct TreeNode {
-    value: String,
-    children: Vec<TreeIndex>,
-    parent: Option<TreeIndex>,
+    pub(super) value: String,
+    pub(super) children: Vec<TreeIndex>,
+    pub(super) parent: Option<TreeIndex>,
 }
 
 impl TreeNode {
-    fn new(value: T) -> Self {
+    pub(super) fn new(value: T) -> Self {
         Self {
             value,
             children: vec![],
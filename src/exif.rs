@@ -1,9 +1,11 @@
+use crate::exiftool;
 use crate::geo::db::LatLng;
+use crate::metadata::{self, Metadata};
 use crate::shell;
 use crate::smalldate::SmallDate;
 use crate::{config, fs::IPathBuf};
 use anyhow::{Context, Result};
-use chrono::{DateTime, Duration, FixedOffset};
+use chrono::{DateTime, Datelike, Duration, FixedOffset, Local, NaiveDateTime, TimeZone};
 use std::path::{Path, PathBuf};
 
 #[derive(PartialEq, Eq, Debug)]
@@ -81,7 +83,7 @@ pub fn add_time(filepath: &Path, minutes: u32) -> Result<()> {
         filename.as_str(),
     ];
 
-    shell::out("exiftool", &args)?;
+    exiftool::out(&args)?;
 
     Ok(())
 }
@@ -96,7 +98,7 @@ pub fn datestr(filepath: &Path, format: &str) -> Result<String> {
         &filepath.to_string(),
     ];
 
-    let out = shell::out("exiftool", exiftool_args)?
+    let out = exiftool::out(exiftool_args)?
         .res
         .context("no exiftool output")?
         .stdout;
@@ -111,7 +113,23 @@ pub fn datestr(filepath: &Path, format: &str) -> Result<String> {
     Ok(datetime.into())
 }
 
+fn native_date(filepath: &Path) -> Option<(NaiveDateTime, Option<FixedOffset>)> {
+    let m = metadata::read(filepath).ok()??;
+    Some((m.date?, m.offset))
+}
+
 pub fn datetime(filepath: &Path) -> Result<DateTime<FixedOffset>> {
+    if let Some((date, offset)) = native_date(filepath) {
+        // like exiftool, dates without a time zone are assumed to be local
+        let offset = match offset {
+            Some(o) => Some(o),
+            None => Local.offset_from_local_datetime(&date).single(),
+        };
+        if let Some(datetime) = offset.and_then(|o| o.from_local_datetime(&date).single()) {
+            return Ok(datetime);
+        }
+    }
+
     let mut datestr = datestr(filepath, "%Y-%m-%dT%H:%M:%S%z")?;
 
     // Convert 2022-10-13T05:23:05-0300 to 2022-10-13T05:23:05-03:00
@@ -126,6 +144,10 @@ pub fn datetime(filepath: &Path) -> Result<DateTime<FixedOffset>> {
 }
 
 pub fn date(filepath: &Path) -> Result<SmallDate> {
+    if let Some((date, _)) = native_date(filepath) {
+        return SmallDate::from_ymd(date.year() as u32, date.month(), date.day());
+    }
+
     let datestr = datestr(filepath, "%y%m%d")?;
     let date = SmallDate::from_str(&datestr)?;
     Ok(date)
@@ -165,7 +187,7 @@ impl Props {
     fn from_str(txt: &str, path: &Path) -> Result<Self> {
         let mut parts = txt.split('\t');
 
-        let orientation_u32: Option<u32> = {
+        let orientation: Option<u32> = {
             let raw = parts.next().context("no orientation")?;
             if is_empty(raw) {
                 None
@@ -186,14 +208,12 @@ impl Props {
             .parse()
             .context("height is not a number")?;
 
-        let millis = {
-            let duration = parts.next().context("no duration")?;
-            if is_empty(duration) {
+        let duration = {
+            let raw = parts.next().context("no duration")?;
+            if is_empty(raw) {
                 None
             } else {
-                let secs_f64 = duration.parse::<f64>()?;
-                let millis = (secs_f64 * 1000.0) as u32;
-                Some(millis)
+                Some(raw.parse::<f64>()?)
             }
         };
 
@@ -210,25 +230,10 @@ impl Props {
             }
         };
 
-        let make = normalize_opt({
-            let make = parts.next().context("no make")?;
-            let android_make = parts.next().context("no android make")?;
-            if !is_empty(make) {
-                make
-            } else {
-                android_make
-            }
-        });
-
-        let model = normalize_opt({
-            let model = parts.next().context("no model")?;
-            let android_model = parts.next().context("no android model")?;
-            if !is_empty(model) {
-                model
-            } else {
-                android_model
-            }
-        });
+        let make = normalize_opt(parts.next().context("no make")?);
+        let android_make = normalize_opt(parts.next().context("no android make")?);
+        let model = normalize_opt(parts.next().context("no model")?);
+        let android_model = normalize_opt(parts.next().context("no android model")?);
 
         let rotation: Option<i32> = {
             let raw = normalize_opt(parts.next().context("no rotation")?);
@@ -238,14 +243,40 @@ impl Props {
             }
         };
 
+        let m = Metadata {
+            orientation,
+            width: Some(width),
+            height: Some(height),
+            duration,
+            lat,
+            make,
+            android_make,
+            model,
+            android_model,
+            rotation,
+            ..Default::default()
+        };
+
+        Self::from_metadata(m, path)
+    }
+
+    fn from_metadata(m: Metadata, path: &Path) -> Result<Self> {
+        let width = m.width.context("no width")?;
+        let height = m.height.context("no height")?;
+
+        let millis = m.duration.map(|secs_f64| (secs_f64 * 1000.0) as u32);
+
+        let make = m.make.filter(|x| !is_empty(x)).or(m.android_make);
+        let model = m.model.filter(|x| !is_empty(x)).or(m.android_model);
+
         let orientation = {
             let is_wider_originally = width > height;
 
             let is_rotated = {
-                match orientation_u32 {
+                match m.orientation {
                     Some(o) => (5..=8).contains(&o),
                     None => {
-                        let degrees = rotation.unwrap_or(0);
+                        let degrees = m.rotation.unwrap_or(0);
                         (degrees / 90) % 2 == 1
                     }
                 }
@@ -261,7 +292,7 @@ impl Props {
 
         let compression_invariant = CompressionInvariantProps {
             millis,
-            lat,
+            lat: m.lat,
             make,
             model,
             orientation,
@@ -271,13 +302,22 @@ impl Props {
             path: path.into(),
             width,
             height,
-            rotation,
+            rotation: m.rotation,
             compression_invariant,
         })
     }
 }
 
+fn native_props(path: &Path) -> Option<Props> {
+    let m = metadata::read(path).ok()??;
+    Props::from_metadata(m, path).ok()
+}
+
 pub fn props(path: &Path) -> Result<Props> {
+    if let Some(props) = native_props(path) {
+        return Ok(props);
+    }
+
     let args = &[
         "-T",
         "-n",
@@ -294,7 +334,7 @@ pub fn props(path: &Path) -> Result<Props> {
         &path.to_string(),
     ];
 
-    shell::out("exiftool", args)?
+    exiftool::out(args)?
         .res
         .context("no exiftool output")
         .and_then(|r| Props::from_str(r.stdout.trim(), path))
@@ -321,15 +361,19 @@ pub fn copy_metadata(from_props: &Props, to: &Path) -> Result<()> {
         a
     };
 
-    shell::out("exiftool", &args)?;
+    exiftool::out(&args)?;
 
     Ok(())
 }
 
 pub fn has_latitude(filepath: &Path) -> Result<bool> {
+    if let Ok(Some(m)) = metadata::read(filepath) {
+        return Ok(m.lat.is_some());
+    }
+
     let exiftool_args = &["-T", "-n", "-gpsLatitude", &filepath.to_string()];
 
-    let out = shell::out("exiftool", exiftool_args)?
+    let out = exiftool::out(exiftool_args)?
         .res
         .context("no exiftool output")?
         .stdout;
@@ -347,9 +391,7 @@ pub fn add_geo(filepath: &Path, latlng: LatLng) -> Result<()> {
         &filepath.to_string(),
     ];
 
-    shell::out("exiftool", exiftool_args)?
-        .res
-        .context("no exiftool output")?;
+    exiftool::out(exiftool_args)?.res.context("no exiftool output")?;
 
     Ok(())
 }
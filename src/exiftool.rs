@@ -0,0 +1,209 @@
+use crate::config;
+use crate::shell::{self, ShellCmd, ShellError, ShellRes};
+use anyhow::{Context, Result};
+use once_cell::sync::OnceCell;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PROGRAM: &str = "exiftool";
+
+static POOL: OnceCell<Pool> = OnceCell::new();
+
+/// A long-lived `exiftool -stay_open True -@ -` process
+struct Process {
+    child: Child,
+    stdin: ChildStdin,
+    stdout: Receiver<String>,
+    stderr: Receiver<String>,
+    requests: u32,
+}
+
+struct Pool {
+    processes: Vec<Mutex<Option<Process>>>,
+    next: AtomicUsize,
+}
+
+fn forward_lines(reader: impl Read + Send + 'static) -> Receiver<String> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        for line in BufReader::new(reader).lines().map_while(Result::ok) {
+            if tx.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    rx
+}
+
+fn read_until(lines: &Receiver<String>, marker: &str, deadline: Instant) -> Result<String> {
+    let mut out = String::new();
+    loop {
+        let timeout = deadline.saturating_duration_since(Instant::now());
+        match lines.recv_timeout(timeout) {
+            Ok(line) if line == marker => return Ok(out),
+            Ok(line) => {
+                out.push_str(&line);
+                out.push('\n');
+            }
+            Err(RecvTimeoutError::Timeout) => return Err(anyhow!("exiftool timed out")),
+            Err(RecvTimeoutError::Disconnected) => return Err(anyhow!("exiftool exited unexpectedly")),
+        }
+    }
+}
+
+impl Process {
+    fn spawn() -> Result<Self> {
+        shell::verify(PROGRAM)?;
+
+        let mut child = Command::new(PROGRAM)
+            .args(["-stay_open", "True", "-@", "-"])
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().context("no exiftool stdin")?;
+        let stdout = forward_lines(child.stdout.take().context("no exiftool stdout")?);
+        let stderr = forward_lines(child.stderr.take().context("no exiftool stderr")?);
+
+        Ok(Self {
+            child,
+            stdin,
+            stdout,
+            stderr,
+            requests: 0,
+        })
+    }
+
+    fn is_alive(&mut self) -> bool {
+        matches!(self.child.try_wait(), Ok(None))
+    }
+
+    /// Args go one per line, followed by markers that exiftool echoes on both
+    /// streams once the request has been processed
+    fn execute(&mut self, args: &[&str], timeout: Duration) -> Result<ShellRes> {
+        self.requests += 1;
+        let marker = format!("{{ready{}}}", self.requests);
+
+        let mut request = String::new();
+        for arg in args {
+            if arg.contains('\n') {
+                return Err(anyhow!("exiftool argument contains a newline: {}", arg));
+            }
+            request.push_str(arg);
+            request.push('\n');
+        }
+        request.push_str(&format!("-echo4\n{}\n-execute{}\n", marker, self.requests));
+
+        self.stdin.write_all(request.as_bytes())?;
+        self.stdin.flush()?;
+
+        let deadline = Instant::now() + timeout;
+        let stdout = read_until(&self.stdout, &marker, deadline)?;
+        let stderr = read_until(&self.stderr, &marker, deadline)?;
+
+        // there's no exit code per request, but errors are always reported this way
+        let code = if stderr.lines().any(|l| l.starts_with("Error")) {
+            1
+        } else {
+            0
+        };
+
+        Ok(ShellRes { code, stdout, stderr })
+    }
+
+    fn close(mut self) {
+        let _ = self.stdin.write_all(b"-stay_open\nFalse\n");
+        let _ = self.stdin.flush();
+        let _ = self.child.wait();
+    }
+
+    fn kill(mut self) {
+        let _ = self.child.kill();
+        let _ = self.child.wait();
+    }
+}
+
+impl Pool {
+    fn new(size: u8) -> Self {
+        Self {
+            processes: (0..size.max(1)).map(|_| Mutex::new(None)).collect(),
+            next: AtomicUsize::new(0),
+        }
+    }
+
+    /// Prefers an idle process, otherwise waits for one in round-robin order
+    fn acquire(&self) -> Result<MutexGuard<'_, Option<Process>>> {
+        let n = self.processes.len();
+        let start = self.next.fetch_add(1, Ordering::Relaxed);
+
+        for i in 0..n {
+            if let Ok(guard) = self.processes[(start + i) % n].try_lock() {
+                return Ok(guard);
+            }
+        }
+
+        self.processes[start % n]
+            .lock()
+            .map_err(|_| ShellError::Lock.into())
+    }
+}
+
+/// Same as `shell::out("exiftool", args)`, but reusing a pool of processes
+pub fn out(args: &[&str]) -> Result<ShellCmd> {
+    let mut cmd = ShellCmd {
+        program: PROGRAM.into(),
+        args: args.iter().map(|x| x.to_string()).collect(),
+        res: None,
+    };
+
+    let pool = POOL.get_or_init(|| Pool::new(config::get().yaml.parallelism.workers));
+    let mut slot = pool.acquire()?;
+
+    if let Some(mut p) = slot.take() {
+        if p.is_alive() {
+            *slot = Some(p);
+        } else {
+            p.kill();
+        }
+    }
+    let process = match slot.as_mut() {
+        Some(p) => p,
+        None => slot.insert(Process::spawn()?),
+    };
+
+    let timeout = Duration::from_secs(config::get().yaml.exiftool.timeout_secs);
+    match process.execute(args, timeout) {
+        Ok(res) => {
+            let is_success = res.code == 0;
+            cmd.res = Some(res);
+            if is_success {
+                Ok(cmd)
+            } else {
+                Err(ShellError::NonZero { cmd }.into())
+            }
+        }
+        Err(e) => {
+            // the next request will spawn a fresh process
+            if let Some(p) = slot.take() {
+                p.kill();
+            }
+            Err(e.context(format!("{:?}", cmd)))
+        }
+    }
+}
+
+pub fn shutdown() {
+    if let Some(pool) = POOL.get() {
+        for slot in &pool.processes {
+            if let Some(p) = slot.lock().ok().and_then(|mut s| s.take()) {
+                p.close();
+            }
+        }
+    }
+}
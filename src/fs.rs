@@ -1,10 +1,11 @@
+use crate::plan::{self, Action};
 use crate::smalldate::SmallDate;
 use crate::{exif, log};
 use anyhow::{Context, Error, Result};
 use directories_next::BaseDirs;
 use std::fmt::Debug;
 use std::fs::{self, File};
-use std::io::{BufRead, BufReader};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 use walkdir::{DirEntry, WalkDir};
@@ -54,6 +55,13 @@ pub fn default_config_pathbuf() -> Result<PathBuf> {
 }
 
 pub fn mv(from: &Path, to: &Path) -> Result<()> {
+    if plan::skip(Action::Move {
+        from: from.into(),
+        to: to.into(),
+    }) {
+        return Ok(());
+    }
+
     remove_file(to)?;
     create_parent_all(to)?;
 
@@ -63,26 +71,72 @@ pub fn mv(from: &Path, to: &Path) -> Result<()> {
     Ok(())
 }
 
+pub fn replace(from: &Path, to: &Path) -> Result<()> {
+    if plan::skip(Action::Move {
+        from: from.into(),
+        to: to.into(),
+    }) {
+        return Ok(());
+    }
+
+    log::warn(&format!("replace {} with {}", to.to_string(), from.to_string()));
+
+    fs::rename(from, to).with_context(|| format!("Failed to replace {}", to.to_string()))
+}
+
 pub fn remove_file(path: &Path) -> Result<()> {
     if !path.exists() {
         return Ok(());
     }
 
+    if plan::skip(Action::RemoveFile { path: path.into() }) {
+        return Ok(());
+    }
+
     log::warn(&format!("remove_file {}", path.to_string()));
 
     fs::remove_file(path).with_context(|| format!("Failed to rm {}", path.to_string()))?;
     Ok(())
 }
 
-pub fn write(to: &Path, txt: &str) -> Result<()> {
+pub fn write(to: &Path, contents: impl AsRef<[u8]>) -> Result<()> {
+    if plan::skip(Action::Write { path: to.into() }) {
+        return Ok(());
+    }
+
     create_parent_all(to)?;
 
     log::warn(&format!("write {}", to.to_string()));
 
-    fs::write(to, txt).with_context(|| format!("Couldn't write to {}", to.to_string()))
+    fs::write(to, contents).with_context(|| format!("Couldn't write to {}", to.to_string()))
+}
+
+pub fn append(to: &Path, txt: &str) -> Result<()> {
+    if plan::skip(Action::Write { path: to.into() }) {
+        return Ok(());
+    }
+
+    create_parent_all(to)?;
+
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(to)
+        .with_context(|| format!("Couldn't open {}", to.to_string()))?;
+
+    file.write_all(txt.as_bytes())
+        .and_then(|_| file.sync_all())
+        .with_context(|| format!("Couldn't append to {}", to.to_string()))
 }
 
 pub fn copy(from: &Path, to: &Path) -> Result<u64> {
+    if plan::skip(Action::Copy {
+        from: from.into(),
+        to: to.into(),
+    }) {
+        return Ok(0);
+    }
+
     remove_file(to)?;
     create_parent_all(to)?;
 
@@ -91,6 +145,20 @@ pub fn copy(from: &Path, to: &Path) -> Result<u64> {
     fs::copy(from, to).with_context(|| format!("Failed to copy {} to {}", from.to_string(), to.to_string()))
 }
 
+pub fn md5(path: &Path) -> Result<String> {
+    let mut file = open(path)?;
+    let mut context = md5::Context::new();
+    let mut buffer = [0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer)?;
+        if n == 0 {
+            break;
+        }
+        context.consume(&buffer[..n]);
+    }
+    Ok(format!("{:x}", context.compute()))
+}
+
 pub fn metadata(filepath: &Path) -> Result<(u32, SmallDate)> {
     let metadata = std::fs::metadata(filepath)?;
     let kb = (metadata.len() / 1024) as u32;
@@ -110,6 +178,10 @@ pub fn remove_dir_all(dir: &Path) -> Result<()> {
         return Ok(());
     }
 
+    if plan::skip(Action::RemoveDir { path: dir.into() }) {
+        return Ok(());
+    }
+
     log::warn(&format!("remove_dir_all {}", dir.to_string()));
 
     fs::remove_dir_all(dir).with_context(|| {
@@ -11,13 +11,18 @@ mod crypto;
 mod db;
 mod env_var;
 mod exif;
+mod exiftool;
 mod format;
 mod fs;
 mod geo;
 mod log;
+mod metadata;
 mod normalize;
+mod notify;
+mod plan;
 mod provider;
 mod rclone;
+mod rekey;
 mod shell;
 mod smalldate;
 mod tasker;
@@ -28,15 +33,34 @@ mod zip;
 
 use std::path::PathBuf;
 
-use crate::{config::Command, fs::IPathBuf};
-use anyhow::Result;
+use crate::config::{Command, DbCommand};
+use crate::fs::IPathBuf;
+use anyhow::{Context, Result};
 
 pub fn handle() -> Result<()> {
-    use Command::*;
     config::setup()?;
     shell::setup()?;
 
-    match config::get().cmd() {
+    let dry_run = config::get().clap.dry_run;
+    if dry_run {
+        plan::setup()?;
+    }
+
+    let result = run(config::get().cmd());
+    exiftool::shutdown();
+    log::finish(&result);
+
+    if dry_run {
+        plan::print(config::get().clap.json)?;
+    }
+
+    result
+}
+
+fn run(cmd: &'static Command) -> Result<()> {
+    use Command::*;
+
+    match cmd {
         SetCameraBuffers => {
             camera::set_buffers()?;
             Ok(())
@@ -64,36 +88,33 @@ pub fn handle() -> Result<()> {
             telegram::upload_buffer()?;
             Ok(())
         }
-        SendTelegramMessage { txt } => {
-            let cmd = telegram::send_message(txt)?;
-            dbg!(&cmd);
-            Ok(())
-        }
-        Password { filename } => {
+        SendTelegramMessage { txt } => telegram::send_message(txt),
+        FetchTelegramFiles {
+            pattern,
+            to,
+            forward_to,
+            copy,
+        } => match (to, forward_to) {
+            (Some(to), None) => telegram::download(pattern, to),
+            (None, Some(chat_id)) => telegram::forward(pattern, chat_id, *copy),
+            _ => Err(anyhow!("either --to or --forward-to must be given")),
+        },
+        Password { filename, scheme } => {
             let password = config::get().crypto_password()?;
-            let out = zip::gen_password(password, filename)?;
+            let scheme = match scheme {
+                Some(v) => crypto::Scheme::from_version(*v)?,
+                None => config::get().password_scheme()?,
+            };
+            let out = zip::gen_password(scheme, password, filename)?;
             println!("{}", out);
             Ok(())
         }
-        UploadTelegramFile { from } => {
-            let cmd = telegram::upload(from)?;
-            dbg!(&cmd);
-            Ok(())
-        }
+        UploadTelegramFile { from } => telegram::upload(from),
         Move {
             provider,
             remote_path,
             local_path,
-        } => {
-            rclone::mv(provider, remote_path, local_path)?;
-            match rclone::rmdirs(provider, remote_path) {
-                Ok(_) => {}
-                Err(e) => {
-                    dbg!(e);
-                }
-            }
-            Ok(())
-        }
+        } => provider::backend(provider)?.mv(remote_path, local_path),
         Debug { query: _ } => {
             log::debug("debug!");
             let path = PathBuf::from("/Users/denis.isidoro/dev/storm/Cargo.toml")
@@ -133,9 +154,99 @@ pub fn handle() -> Result<()> {
             provider,
             remote_path,
         } => {
-            let cmd = rclone::push(from, provider, remote_path.clone())?;
-            dbg!(&cmd);
+            let mut remote = provider::remote_path(provider, remote_path.clone())?;
+            if from.is_file() {
+                remote.push(from.file_name().context("no filename")?);
+            }
+            provider::backend(provider)?.put(from, &remote)
+        }
+        Restore {
+            pattern,
+            to,
+            db_folder,
+        } => archive::restore(pattern, to, db_folder_or_default(db_folder)?),
+        Verify { download, db_folder } => archive::verify(db_folder_or_default(db_folder)?, *download),
+        Rekey {
+            provider,
+            local,
+            checkpoint,
+        } => {
+            let source = match (provider, local) {
+                (Some(p), None) => rekey::Source::Provider(p),
+                (None, Some(l)) => rekey::Source::Local(l),
+                _ => return Err(anyhow!("either --provider or --local must be given")),
+            };
+            rekey::rekey(source, checkpoint.clone())
+        }
+        Db { cmd } => handle_db(cmd),
+    }
+}
+
+fn db_folder_or_default(db_folder: &Option<PathBuf>) -> Result<&PathBuf> {
+    match db_folder {
+        Some(p) => Ok(p),
+        None => archive::get_db_folder(),
+    }
+}
+
+fn handle_db(cmd: &DbCommand) -> Result<()> {
+    use DbCommand::*;
+
+    match cmd {
+        Query {
+            glob,
+            regex,
+            from,
+            to,
+            min_kb,
+            max_kb,
+            db_folder,
+        } => {
+            let query = db::query::Query::new(
+                glob.as_deref(),
+                regex.as_deref(),
+                from.as_deref(),
+                to.as_deref(),
+                *min_kb,
+                *max_kb,
+            )?;
+            for m in db::query::run(db_folder_or_default(db_folder)?, &query)? {
+                println!("{}", m);
+            }
+            Ok(())
+        }
+        Convert { from, to } => db::convert(from, to),
+        Diff { old, new } => {
+            for change in db::diff::diff(&db::read(old)?, &db::read(new)?) {
+                println!("{}", change);
+            }
             Ok(())
         }
+        Merge {
+            base,
+            other,
+            output,
+            policy,
+        } => {
+            let policy = db::diff::MergePolicy::from_str(policy)?;
+            let merged = db::diff::merge(&db::read(base)?, &db::read(other)?, policy)?;
+            db::write(merged, output)
+        }
+        Fsck { db_folder, fix } => {
+            let reports = db::fsck::run(db_folder_or_default(db_folder)?, *fix)?;
+            let mut broken = 0;
+            for report in reports.iter().filter(|r| !r.problems.is_empty()) {
+                print!("{}", report);
+                if !(*fix && report.is_repairable()) {
+                    broken += 1;
+                }
+            }
+            if broken > 0 {
+                Err(anyhow!("{} of {} catalogs have problems", broken, reports.len()))
+            } else {
+                Ok(())
+            }
+        }
+        Rebuild { db_folder, overwrite } => archive::rebuild(db_folder_or_default(db_folder)?, *overwrite),
     }
 }
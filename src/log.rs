@@ -1,4 +1,5 @@
 use crate::log;
+use crate::notify::{self, Event};
 use crate::tasker;
 use anyhow::Error;
 use anyhow::Result;
@@ -14,6 +15,8 @@ pub struct Logger {
     label: String,
     n: usize,
     processing: HashSet<usize>,
+    succeeded: usize,
+    failed: usize,
 }
 
 impl Logger {
@@ -61,16 +64,25 @@ fn get() -> MutexGuard<'static, Logger> {
 static INSTANCE: OnceCell<Mutex<Logger>> = OnceCell::new();
 
 pub fn setup(label: String, n: usize) -> Result<()> {
+    let event = Event::RunStarted {
+        label: label.clone(),
+        files: n,
+    };
+
     let logger = Logger {
         label,
         n,
         processing: HashSet::new(),
+        succeeded: 0,
+        failed: 0,
     };
 
     INSTANCE
         .set(Mutex::new(logger))
         .map_err(|_| anyhow!("unable to set logger"))?;
 
+    notify::send(&event);
+
     Ok(())
 }
 
@@ -79,11 +91,44 @@ pub fn start(i: usize) {
 }
 
 pub fn success(i: usize) {
-    get().step(false, i, &["success"])
+    let mut logger = get();
+    logger.succeeded += 1;
+    logger.step(false, i, &["success"])
 }
 
 pub fn failure(i: usize, error: Error) {
-    get().step(false, i, &["error", &error.to_string()])
+    // the lock is released before notifying, as sinks can be slow
+    let label = {
+        let mut logger = get();
+        logger.failed += 1;
+        logger.step(false, i, &["error", &error.to_string()]);
+        logger.label.clone()
+    };
+
+    notify::send(&Event::FileFailed {
+        label,
+        file: i,
+        error: format!("{:#}", error),
+    });
+}
+
+/// Notifies how the run went, for commands that processed files
+pub fn finish(result: &Result<()>) {
+    let event = match INSTANCE.get() {
+        Some(logger) => {
+            let logger = logger.lock().expect("failed to lock logger");
+            Event::RunSummary {
+                label: logger.label.clone(),
+                files: logger.n,
+                succeeded: logger.succeeded,
+                failed: logger.failed,
+                error: result.as_ref().err().map(|e| format!("{:#}", e)),
+            }
+        }
+        None => return,
+    };
+
+    notify::send(&event);
 }
 
 fn write_raw(color: Color, msg: &str) -> io::Result<()> {
@@ -0,0 +1,107 @@
+use super::{tiff, xmp, Metadata};
+use anyhow::{Context, Result};
+use std::fs::File;
+use std::io::{BufReader, Read};
+
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+
+const APP1: u8 = 0xE1;
+const SOS: u8 = 0xDA;
+const EOI: u8 = 0xD9;
+
+fn is_start_of_frame(marker: u8) -> bool {
+    (0xC0..=0xCF).contains(&marker) && !matches!(marker, 0xC4 | 0xC8 | 0xCC)
+}
+
+fn has_no_length(marker: u8) -> bool {
+    matches!(marker, 0x01 | 0xD0..=0xD8)
+}
+
+fn read_u16(reader: &mut impl Read) -> Result<u16> {
+    let mut buf = [0u8; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+/// Walks the segments up to the image data
+pub(super) fn read(file: &mut File, m: &mut Metadata) -> Result<()> {
+    let mut reader = BufReader::new(file);
+
+    loop {
+        let mut byte = [0u8; 1];
+        reader.read_exact(&mut byte)?;
+        if byte[0] != 0xFF {
+            return Err(anyhow!("invalid JPEG segment"));
+        }
+
+        let mut marker = 0xFF;
+        while marker == 0xFF {
+            reader.read_exact(&mut byte)?;
+            marker = byte[0];
+        }
+
+        if has_no_length(marker) {
+            continue;
+        }
+        if marker == SOS || marker == EOI {
+            break;
+        }
+
+        let len = read_u16(&mut reader)?
+            .checked_sub(2)
+            .context("invalid JPEG segment length")?;
+        let mut data = vec![0u8; len as usize];
+        reader.read_exact(&mut data)?;
+
+        if marker == APP1 && data.starts_with(EXIF_HEADER) {
+            tiff::parse(&data[EXIF_HEADER.len()..], m, false)?;
+        } else if marker == APP1 && data.starts_with(XMP_HEADER) {
+            xmp::parse(&String::from_utf8_lossy(&data[XMP_HEADER.len()..]), m);
+        } else if is_start_of_frame(marker) && data.len() >= 5 {
+            m.height = Some(u16::from_be_bytes([data[1], data[2]]).into());
+            m.width = Some(u16::from_be_bytes([data[3], data[4]]).into());
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn segment(marker: u8, data: &[u8]) -> Vec<u8> {
+        let mut v = vec![0xFF, marker];
+        v.extend((data.len() as u16 + 2).to_be_bytes());
+        v.extend(data);
+        v
+    }
+
+    #[test]
+    fn test_read() {
+        let mut exif = EXIF_HEADER.to_vec();
+        exif.extend(tiff::tests::sample());
+
+        let mut xmp = XMP_HEADER.to_vec();
+        xmp.extend(br#"<rdf:Description xmp:AndroidModel="MYCAMERA" exif:GPSLatitude="20,0N"/>"#);
+
+        let mut jpeg = vec![0xFF, 0xD8];
+        jpeg.extend(segment(APP1, &exif));
+        jpeg.extend(segment(APP1, &xmp));
+        jpeg.extend(segment(0xC2, &[8, 0, 240, 1, 64, 3]));
+        jpeg.extend(segment(SOS, &[0; 10]));
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&jpeg).unwrap();
+
+        let m = crate::metadata::read(tmp.path()).unwrap().unwrap();
+        assert_eq!(m.width, Some(320));
+        assert_eq!(m.height, Some(240));
+        assert_eq!(m.make.as_deref(), Some("samsung"));
+        assert_eq!(m.android_model.as_deref(), Some("MYCAMERA"));
+        // EXIF takes precedence over XMP
+        assert_eq!(m.lat, Some(-10.5));
+    }
+}
@@ -0,0 +1,140 @@
+mod jpeg;
+mod mp4;
+mod png;
+mod tiff;
+mod xmp;
+
+use crate::fs;
+use anyhow::Result;
+use chrono::{FixedOffset, NaiveDateTime};
+use std::io::Read;
+use std::path::Path;
+
+/// Raw tag values, named after their exiftool counterparts
+#[derive(Default, Debug, Clone)]
+pub struct Metadata {
+    pub orientation: Option<u32>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub duration: Option<f64>,
+    pub lat: Option<f32>,
+    pub make: Option<String>,
+    pub android_make: Option<String>,
+    pub model: Option<String>,
+    pub android_model: Option<String>,
+    pub rotation: Option<i32>,
+    pub date: Option<NaiveDateTime>,
+    pub offset: Option<FixedOffset>,
+}
+
+enum Kind {
+    Jpeg,
+    Png,
+    Tiff,
+    Mp4,
+}
+
+fn kind(magic: &[u8]) -> Option<Kind> {
+    if magic.starts_with(&[0xFF, 0xD8]) {
+        return Some(Kind::Jpeg);
+    }
+    if magic.starts_with(png::SIGNATURE) {
+        return Some(Kind::Png);
+    }
+    if magic.starts_with(b"II*\0") || magic.starts_with(b"MM\0*") {
+        return Some(Kind::Tiff);
+    }
+    if magic.len() >= 8 && mp4::is_top_level(&magic[4..8]) {
+        return Some(Kind::Mp4);
+    }
+    None
+}
+
+/// Returns None for formats that can't be parsed natively
+pub fn read(path: &Path) -> Result<Option<Metadata>> {
+    let mut file = fs::open(path)?;
+
+    let mut magic = [0u8; 12];
+    let n = file.read(&mut magic)?;
+    let kind = match kind(&magic[..n]) {
+        Some(k) => k,
+        None => return Ok(None),
+    };
+
+    let mut file = fs::open(path)?;
+    let mut m = Metadata::default();
+
+    match kind {
+        Kind::Jpeg => jpeg::read(&mut file, &mut m)?,
+        Kind::Png => png::read(&mut file, &mut m)?,
+        Kind::Tiff => {
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            tiff::parse(&data, &mut m, true)?;
+        }
+        Kind::Mp4 => mp4::read(&mut file, &mut m)?,
+    }
+
+    Ok(Some(m))
+}
+
+fn non_empty(txt: &str) -> Option<String> {
+    let trimmed = txt.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if trimmed.is_empty() {
+        None
+    } else {
+        Some(trimmed.into())
+    }
+}
+
+fn set_if_none<T>(field: &mut Option<T>, value: Option<T>) {
+    if field.is_none() {
+        *field = value;
+    }
+}
+
+fn parse_date(txt: &str) -> Option<NaiveDateTime> {
+    let txt = txt.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    let prefix = txt.get(0..19)?;
+    NaiveDateTime::parse_from_str(prefix, "%Y:%m:%d %H:%M:%S")
+        .or_else(|_| NaiveDateTime::parse_from_str(prefix, "%Y-%m-%dT%H:%M:%S"))
+        .ok()
+}
+
+fn parse_offset(txt: &str) -> Option<FixedOffset> {
+    let txt = txt.trim_matches(|c: char| c == '\0' || c.is_whitespace());
+    if txt == "Z" {
+        return FixedOffset::east_opt(0);
+    }
+    let sign = match txt.chars().next()? {
+        '+' => 1,
+        '-' => -1,
+        _ => return None,
+    };
+    let mut parts = txt[1..].split(':');
+    let hours: i32 = parts.next()?.parse().ok()?;
+    let minutes: i32 = parts.next().unwrap_or("0").parse().ok()?;
+    FixedOffset::east_opt(sign * (hours * 3600 + minutes * 60))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_date() {
+        let expected = NaiveDateTime::parse_from_str("2022-10-13 05:23:05", "%Y-%m-%d %H:%M:%S").ok();
+        assert_eq!(parse_date("2022:10:13 05:23:05\0"), expected);
+        assert_eq!(parse_date("2022-10-13T05:23:05-03:00"), expected);
+        assert_eq!(parse_date("0000:00:00 00:00:00"), None);
+        assert_eq!(parse_date("foo"), None);
+    }
+
+    #[test]
+    fn test_parse_offset() {
+        assert_eq!(parse_offset("-03:00"), FixedOffset::east_opt(-3 * 3600));
+        assert_eq!(parse_offset("+05:30"), FixedOffset::east_opt(5 * 3600 + 30 * 60));
+        assert_eq!(parse_offset("Z"), FixedOffset::east_opt(0));
+        assert_eq!(parse_offset("    "), None);
+    }
+}
@@ -0,0 +1,425 @@
+use super::{non_empty, set_if_none, xmp, Metadata};
+use crate::smalldate;
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+const XMP_UUID: [u8; 16] = [
+    0xBE, 0x7A, 0xCF, 0xCB, 0x97, 0xA9, 0x42, 0xE8, 0x9C, 0x71, 0x99, 0x94, 0x91, 0xE3, 0xAF, 0xAC,
+];
+
+/// Seconds between 1904-01-01 and 1970-01-01
+const EPOCH_OFFSET: u64 = 2_082_844_800;
+
+const MAX_XMP_LEN: u64 = 4 * 1024 * 1024;
+
+pub(super) fn is_top_level(kind: &[u8]) -> bool {
+    matches!(
+        kind,
+        b"ftyp" | b"moov" | b"mdat" | b"wide" | b"free" | b"skip" | b"pnot"
+    )
+}
+
+#[derive(Default)]
+struct Track {
+    handler: Vec<u8>,
+    width: Option<u32>,
+    height: Option<u32>,
+    rotation: Option<i32>,
+    date: Option<NaiveDateTime>,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+fn u64_at(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(offset..offset + 8)?.try_into().ok()?))
+}
+
+/// Splits a box payload into its children, as (kind, payload) pairs
+fn children(data: &[u8]) -> Vec<(&[u8], &[u8])> {
+    let mut boxes = vec![];
+    let mut offset = 0;
+
+    while offset + 8 <= data.len() {
+        let kind = &data[offset + 4..offset + 8];
+        let (header, size) = match u32_at(data, offset).unwrap_or(0) {
+            0 => (8, data.len() - offset),
+            1 => match u64_at(data, offset + 8) {
+                Some(s) => (16, s as usize),
+                None => break,
+            },
+            s => (8, s as usize),
+        };
+        if size < header || offset + size > data.len() {
+            break;
+        }
+        boxes.push((kind, &data[offset + header..offset + size]));
+        offset += size;
+    }
+
+    boxes
+}
+
+fn date(seconds_since_1904: u64) -> Option<NaiveDateTime> {
+    let unix = seconds_since_1904.checked_sub(EPOCH_OFFSET)?;
+    if unix == 0 {
+        return None;
+    }
+    smalldate::naive_from_timestamp(unix as i64)
+}
+
+/// Creation time of mvhd, tkhd and mdhd boxes
+fn creation_date(payload: &[u8]) -> Option<NaiveDateTime> {
+    match payload.first()? {
+        1 => date(u64_at(payload, 4)?),
+        _ => date(u32_at(payload, 4)?.into()),
+    }
+}
+
+fn movie_header(payload: &[u8], m: &mut Metadata) -> Option<()> {
+    let (timescale, duration) = match payload.first()? {
+        1 => (u32_at(payload, 20)?, u64_at(payload, 24)?),
+        _ => (u32_at(payload, 12)?, u32_at(payload, 16)?.into()),
+    };
+    if timescale > 0 {
+        m.duration = Some(duration as f64 / f64::from(timescale));
+    }
+    Some(())
+}
+
+fn track_header(payload: &[u8], track: &mut Track) -> Option<()> {
+    let matrix = match payload.first()? {
+        1 => 52,
+        _ => 40,
+    };
+    let a = u32_at(payload, matrix)? as i32;
+    let b = u32_at(payload, matrix + 4)? as i32;
+    let degrees = f64::from(b).atan2(f64::from(a)).to_degrees().round() as i32;
+    track.rotation = Some((degrees + 360) % 360);
+    // 16.16 fixed point
+    track.width = Some(u32_at(payload, matrix + 36)? >> 16);
+    track.height = Some(u32_at(payload, matrix + 40)? >> 16);
+    Some(())
+}
+
+fn track(payload: &[u8]) -> Track {
+    let mut track = Track::default();
+
+    for (kind, payload) in children(payload) {
+        match kind {
+            b"tkhd" => {
+                track_header(payload, &mut track);
+            }
+            b"mdia" => {
+                for (kind, payload) in children(payload) {
+                    match kind {
+                        b"hdlr" => track.handler = payload.get(8..12).unwrap_or_default().to_vec(),
+                        b"mdhd" => track.date = creation_date(payload),
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    track
+}
+
+/// "+10.5000-084.6800/" to 10.5
+fn iso6709_latitude(raw: &str) -> Option<f32> {
+    let end = raw
+        .char_indices()
+        .skip(1)
+        .find(|(_, c)| *c == '+' || *c == '-')
+        .map(|(i, _)| i)?;
+    raw[..end].parse().ok()
+}
+
+fn apply(key: &str, value: &[u8], m: &mut Metadata) {
+    let text = non_empty(&String::from_utf8_lossy(value));
+    match key {
+        "©mak" | "com.apple.quicktime.make" => set_if_none(&mut m.make, text),
+        "©mod" | "com.apple.quicktime.model" => set_if_none(&mut m.model, text),
+        "com.android.manufacturer" => set_if_none(&mut m.android_make, text),
+        "com.android.model" => set_if_none(&mut m.android_model, text),
+        "©xyz" | "com.apple.quicktime.location.ISO6709" => {
+            set_if_none(&mut m.lat, text.as_deref().and_then(iso6709_latitude))
+        }
+        _ => (),
+    }
+}
+
+fn key_name(kind: &[u8]) -> String {
+    match kind.split_first() {
+        Some((0xA9, rest)) => format!("©{}", String::from_utf8_lossy(rest)),
+        _ => String::from_utf8_lossy(kind).into(),
+    }
+}
+
+/// Value of a "data" box: type indicator and locale, then the value
+fn data_value(payload: &[u8]) -> Option<&[u8]> {
+    children(payload)
+        .into_iter()
+        .find(|(kind, _)| kind == b"data")
+        .and_then(|(_, data)| data.get(8..))
+}
+
+/// QuickTime user data text: length and language, then the text
+fn user_data_text(payload: &[u8]) -> Option<&[u8]> {
+    if payload.get(4..8) == Some(b"data") {
+        return data_value(payload);
+    }
+    let len = u16::from_be_bytes(payload.get(0..2)?.try_into().ok()?) as usize;
+    payload.get(4..4 + len)
+}
+
+fn keys(payload: &[u8]) -> Vec<String> {
+    let count = u32_at(payload, 4).unwrap_or(0);
+    let mut offset = 8;
+    let mut keys = vec![];
+
+    for _ in 0..count {
+        let size = match u32_at(payload, offset) {
+            Some(s) if s >= 8 => s as usize,
+            _ => break,
+        };
+        match payload.get(offset + 8..offset + size) {
+            Some(name) => keys.push(String::from_utf8_lossy(name).into()),
+            None => break,
+        }
+        offset += size;
+    }
+
+    keys
+}
+
+fn meta(payload: &[u8], m: &mut Metadata) {
+    // ISO meta is a full box, QuickTime's isn't
+    let payload = if payload.get(4..8) == Some(b"hdlr") {
+        payload
+    } else {
+        payload.get(4..).unwrap_or_default()
+    };
+
+    let boxes = children(payload);
+    let keys = boxes
+        .iter()
+        .find(|(kind, _)| kind == b"keys")
+        .map(|(_, payload)| keys(payload))
+        .unwrap_or_default();
+
+    for (kind, payload) in boxes {
+        if kind != b"ilst" {
+            continue;
+        }
+        for (item, payload) in children(payload) {
+            let key = match u32_at(item, 0) {
+                Some(i) if i >= 1 && (i as usize) <= keys.len() => keys[i as usize - 1].clone(),
+                _ => key_name(item),
+            };
+            if let Some(value) = data_value(payload) {
+                apply(&key, value, m);
+            }
+        }
+    }
+}
+
+fn user_data(payload: &[u8], m: &mut Metadata) {
+    for (kind, payload) in children(payload) {
+        match kind {
+            b"meta" => meta(payload, m),
+            b"XMP_" => xmp::parse(&String::from_utf8_lossy(payload), m),
+            _ => {
+                if let Some(value) = user_data_text(payload) {
+                    apply(&key_name(kind), value, m);
+                }
+            }
+        }
+    }
+}
+
+fn movie(payload: &[u8], m: &mut Metadata) {
+    let mut movie_date = None;
+    let mut tracks = vec![];
+
+    for (kind, payload) in children(payload) {
+        match kind {
+            b"mvhd" => {
+                movie_header(payload, m);
+                movie_date = creation_date(payload);
+            }
+            b"trak" => tracks.push(track(payload)),
+            b"udta" => user_data(payload, m),
+            b"meta" => meta(payload, m),
+            _ => (),
+        }
+    }
+
+    let video = tracks.into_iter().find(|t| t.handler == b"vide");
+    if let Some(v) = &video {
+        m.width = v.width;
+        m.height = v.height;
+        m.rotation = v.rotation;
+    }
+    set_if_none(&mut m.date, video.and_then(|v| v.date).or(movie_date));
+}
+
+/// Walks the top-level boxes, loading only moov and XMP into memory
+pub(super) fn read(file: &mut File, m: &mut Metadata) -> Result<()> {
+    let file_len = file.metadata()?.len();
+    let mut offset = 0;
+    let mut found_movie = false;
+
+    while offset + 8 <= file_len {
+        file.seek(SeekFrom::Start(offset))?;
+        let mut header = [0u8; 16];
+        file.read_exact(&mut header[..8])?;
+        let kind = [header[4], header[5], header[6], header[7]];
+
+        let (header_len, size) = match u32_at(&header, 0).unwrap_or(0) {
+            0 => (8, file_len - offset),
+            1 => {
+                file.read_exact(&mut header[8..])?;
+                (16, u64_at(&header, 8).context("invalid box size")?)
+            }
+            s => (8, u64::from(s)),
+        };
+        if size < header_len || offset + size > file_len {
+            break;
+        }
+        let payload_len = (size - header_len) as usize;
+
+        match &kind {
+            b"moov" => {
+                let mut payload = vec![0u8; payload_len];
+                file.read_exact(&mut payload)?;
+                movie(&payload, m);
+                found_movie = true;
+            }
+            b"uuid" if (payload_len as u64) <= MAX_XMP_LEN => {
+                let mut payload = vec![0u8; payload_len];
+                file.read_exact(&mut payload)?;
+                if let Some(xmp) = payload.strip_prefix(&XMP_UUID) {
+                    xmp::parse(&String::from_utf8_lossy(xmp), m);
+                }
+            }
+            _ => (),
+        }
+
+        offset += size;
+    }
+
+    if found_movie {
+        Ok(())
+    } else {
+        Err(anyhow!("no moov box"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn mp4_box(kind: &[u8], payload: &[u8]) -> Vec<u8> {
+        let mut v = (payload.len() as u32 + 8).to_be_bytes().to_vec();
+        v.extend(kind);
+        v.extend(payload);
+        v
+    }
+
+    fn concat(parts: &[Vec<u8>]) -> Vec<u8> {
+        parts.concat()
+    }
+
+    fn sample() -> Vec<u8> {
+        let created = (EPOCH_OFFSET + 1_665_638_585).to_be_bytes();
+
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend(created);
+        mvhd.extend([0; 8]);
+        mvhd.extend(1000u32.to_be_bytes());
+        mvhd.extend(3500u64.to_be_bytes());
+
+        // rotated by 90 degrees
+        let mut tkhd = vec![0; 40];
+        for value in [0i32, 0x10000, 0, -0x10000, 0, 0, 0, 0, 0x4000_0000] {
+            tkhd.extend(value.to_be_bytes());
+        }
+        tkhd.extend((1920u32 << 16).to_be_bytes());
+        tkhd.extend((1080u32 << 16).to_be_bytes());
+
+        let mut hdlr = vec![0; 8];
+        hdlr.extend(b"vide");
+        hdlr.extend([0; 12]);
+
+        let mut mdhd = vec![0, 0, 0, 0];
+        mdhd.extend(((EPOCH_OFFSET + 1_665_638_585) as u32).to_be_bytes());
+        mdhd.extend([0; 16]);
+
+        let mdia = mp4_box(
+            b"mdia",
+            &concat(&[mp4_box(b"hdlr", &hdlr), mp4_box(b"mdhd", &mdhd)]),
+        );
+        let trak = mp4_box(b"trak", &concat(&[mp4_box(b"tkhd", &tkhd), mdia]));
+
+        let mut xyz = 18u16.to_be_bytes().to_vec();
+        xyz.extend([0x15, 0xC7]);
+        xyz.extend(b"+10.5000-084.6800/");
+        let mut mak = 7u16.to_be_bytes().to_vec();
+        mak.extend([0x15, 0xC7]);
+        mak.extend(b"samsung");
+        let udta = mp4_box(
+            b"udta",
+            &concat(&[mp4_box(b"\xA9xyz", &xyz), mp4_box(b"\xA9mak", &mak)]),
+        );
+
+        let mut keys = vec![0, 0, 0, 0];
+        keys.extend(1u32.to_be_bytes());
+        keys.extend(mp4_box(b"mdta", b"com.android.model"));
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend(b"MYCAMERA");
+        let ilst = mp4_box(b"ilst", &mp4_box(&1u32.to_be_bytes(), &mp4_box(b"data", &data)));
+        let mut meta = mp4_box(b"hdlr", &[0; 24]);
+        meta.extend(mp4_box(b"keys", &keys));
+        meta.extend(ilst);
+
+        let moov = mp4_box(
+            b"moov",
+            &concat(&[mp4_box(b"mvhd", &mvhd), trak, udta, mp4_box(b"meta", &meta)]),
+        );
+
+        concat(&[
+            mp4_box(b"ftyp", b"isom\0\0\0\0"),
+            mp4_box(b"mdat", &[0; 64]),
+            moov,
+        ])
+    }
+
+    #[test]
+    fn test_read() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&sample()).unwrap();
+
+        let m = crate::metadata::read(tmp.path()).unwrap().unwrap();
+        assert_eq!(m.width, Some(1920));
+        assert_eq!(m.height, Some(1080));
+        assert_eq!(m.rotation, Some(90));
+        assert_eq!(m.duration, Some(3.5));
+        assert_eq!(m.lat, Some(10.5));
+        assert_eq!(m.make.as_deref(), Some("samsung"));
+        assert_eq!(m.android_model.as_deref(), Some("MYCAMERA"));
+        assert_eq!(m.date, smalldate::naive_from_timestamp(1_665_638_585));
+    }
+
+    #[test]
+    fn test_no_movie() {
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&mp4_box(b"ftyp", b"heic\0\0\0\0")).unwrap();
+        assert!(crate::metadata::read(tmp.path()).is_err());
+    }
+}
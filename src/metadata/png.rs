@@ -0,0 +1,106 @@
+use super::{tiff, xmp, Metadata};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+
+pub(super) const SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+const XMP_KEYWORD: &[u8] = b"XML:com.adobe.xmp";
+
+/// Extracts the text of an uncompressed iTXt chunk
+fn international_text<'a>(data: &'a [u8], keyword: &[u8]) -> Option<&'a [u8]> {
+    let rest = data.strip_prefix(keyword)?.strip_prefix(&[0])?;
+    let (compressed, rest) = rest.split_first()?;
+    if *compressed != 0 {
+        return None;
+    }
+    // compression method, language tag and translated keyword
+    let mut rest = rest.get(1..)?;
+    for _ in 0..2 {
+        let end = rest.iter().position(|b| *b == 0)?;
+        rest = &rest[end + 1..];
+    }
+    Some(rest)
+}
+
+/// Walks the chunks, skipping image data
+pub(super) fn read(file: &mut File, m: &mut Metadata) -> Result<()> {
+    file.seek(SeekFrom::Start(SIGNATURE.len() as u64))?;
+
+    loop {
+        let mut header = [0u8; 8];
+        if file.read_exact(&mut header).is_err() {
+            break;
+        }
+        let len = u32::from_be_bytes([header[0], header[1], header[2], header[3]]);
+        let kind = &header[4..8];
+
+        if !matches!(kind, b"IHDR" | b"eXIf" | b"iTXt") {
+            if kind == b"IEND" {
+                break;
+            }
+            file.seek(SeekFrom::Current(i64::from(len) + 4))?;
+            continue;
+        }
+
+        let mut data = vec![0u8; len as usize];
+        file.read_exact(&mut data)?;
+        file.seek(SeekFrom::Current(4))?;
+
+        match kind {
+            b"IHDR" if data.len() >= 8 => {
+                m.width = Some(u32::from_be_bytes([data[0], data[1], data[2], data[3]]));
+                m.height = Some(u32::from_be_bytes([data[4], data[5], data[6], data[7]]));
+            }
+            b"eXIf" => tiff::parse(&data, m, false)?,
+            b"iTXt" => {
+                if let Some(text) = international_text(&data, XMP_KEYWORD) {
+                    xmp::parse(&String::from_utf8_lossy(text), m);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn chunk(kind: &[u8], data: &[u8]) -> Vec<u8> {
+        let mut v = (data.len() as u32).to_be_bytes().to_vec();
+        v.extend(kind);
+        v.extend(data);
+        v.extend([0; 4]);
+        v
+    }
+
+    #[test]
+    fn test_read() {
+        let mut ihdr = 640u32.to_be_bytes().to_vec();
+        ihdr.extend(480u32.to_be_bytes());
+        ihdr.extend([8, 2, 0, 0, 0]);
+
+        let mut itxt = XMP_KEYWORD.to_vec();
+        itxt.extend(b"\0\0\0en\0\0");
+        itxt.extend(br#"<x tiff:Model="MYCAMERA"/>"#);
+
+        let mut png = SIGNATURE.to_vec();
+        png.extend(chunk(b"IHDR", &ihdr));
+        png.extend(chunk(b"IDAT", &[0; 32]));
+        png.extend(chunk(b"iTXt", &itxt));
+        png.extend(chunk(b"IEND", &[]));
+
+        let mut tmp = tempfile::NamedTempFile::new().unwrap();
+        tmp.write_all(&png).unwrap();
+
+        let m = crate::metadata::read(tmp.path()).unwrap().unwrap();
+        assert_eq!(m.width, Some(640));
+        assert_eq!(m.height, Some(480));
+        assert_eq!(m.model.as_deref(), Some("MYCAMERA"));
+        assert_eq!(m.orientation, None);
+    }
+}
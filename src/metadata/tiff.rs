@@ -0,0 +1,268 @@
+use super::{non_empty, parse_date, parse_offset, set_if_none, Metadata};
+use anyhow::{Context, Result};
+
+const IMAGE_WIDTH: u16 = 0x0100;
+const IMAGE_HEIGHT: u16 = 0x0101;
+const MAKE: u16 = 0x010F;
+const MODEL: u16 = 0x0110;
+const ORIENTATION: u16 = 0x0112;
+const EXIF_IFD: u16 = 0x8769;
+const GPS_IFD: u16 = 0x8825;
+const DATE_TIME_ORIGINAL: u16 = 0x9003;
+const OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const GPS_LATITUDE_REF: u16 = 0x0001;
+const GPS_LATITUDE: u16 = 0x0002;
+
+const MAX_ENTRIES: usize = 1024;
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    value_offset: usize,
+}
+
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+fn kind_size(kind: u16) -> u32 {
+    match kind {
+        1 | 2 | 6 | 7 => 1,
+        3 | 8 => 2,
+        4 | 9 | 11 => 4,
+        5 | 10 | 12 => 8,
+        _ => 0,
+    }
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Result<Self> {
+        let little_endian = match data.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err(anyhow!("invalid TIFF byte order")),
+        };
+        let tiff = Self { data, little_endian };
+        if tiff.u16(2) != Some(42) {
+            return Err(anyhow!("invalid TIFF magic number"));
+        }
+        Ok(tiff)
+    }
+
+    fn bytes<const N: usize>(&self, offset: usize) -> Option<[u8; N]> {
+        self.data.get(offset..offset.checked_add(N)?)?.try_into().ok()
+    }
+
+    fn u16(&self, offset: usize) -> Option<u16> {
+        let b = self.bytes(offset)?;
+        Some(if self.little_endian {
+            u16::from_le_bytes(b)
+        } else {
+            u16::from_be_bytes(b)
+        })
+    }
+
+    fn u32(&self, offset: usize) -> Option<u32> {
+        let b = self.bytes(offset)?;
+        Some(if self.little_endian {
+            u32::from_le_bytes(b)
+        } else {
+            u32::from_be_bytes(b)
+        })
+    }
+
+    fn ifd(&self, offset: usize) -> Vec<Entry> {
+        let count = self.u16(offset).unwrap_or(0) as usize;
+        (0..count.min(MAX_ENTRIES))
+            .map_while(|i| {
+                let at = offset + 2 + i * 12;
+                let tag = self.u16(at)?;
+                let kind = self.u16(at + 2)?;
+                let count = self.u32(at + 4)?;
+                let value_offset = if kind_size(kind).saturating_mul(count) <= 4 {
+                    at + 8
+                } else {
+                    self.u32(at + 8)? as usize
+                };
+                Some(Entry {
+                    tag,
+                    kind,
+                    count,
+                    value_offset,
+                })
+            })
+            .collect()
+    }
+
+    fn unsigned(&self, entry: &Entry) -> Option<u32> {
+        match entry.kind {
+            3 => self.u16(entry.value_offset).map(u32::from),
+            4 => self.u32(entry.value_offset),
+            _ => None,
+        }
+    }
+
+    fn ascii(&self, entry: &Entry) -> Option<String> {
+        let end = entry.value_offset.checked_add(entry.count as usize)?;
+        let raw = self.data.get(entry.value_offset..end)?;
+        non_empty(&String::from_utf8_lossy(raw))
+    }
+
+    fn rationals(&self, entry: &Entry) -> Option<Vec<f64>> {
+        if entry.kind != 5 {
+            return None;
+        }
+        (0..entry.count as usize)
+            .map(|i| {
+                let at = entry.value_offset + i * 8;
+                let numerator = self.u32(at)?;
+                let denominator = self.u32(at + 4)?;
+                if denominator == 0 {
+                    None
+                } else {
+                    Some(f64::from(numerator) / f64::from(denominator))
+                }
+            })
+            .collect()
+    }
+}
+
+fn latitude(degrees_minutes_seconds: &[f64], reference: Option<String>) -> Option<f32> {
+    let mut lat = 0.0;
+    for (value, divisor) in degrees_minutes_seconds.iter().zip([1.0, 60.0, 3600.0]) {
+        lat += value / divisor;
+    }
+    if reference.as_deref() == Some("S") {
+        lat = -lat;
+    }
+    Some(lat as f32)
+}
+
+/// Fills the metadata with the tags found in a TIFF structure, as embedded in
+/// JPEG APP1 segments, PNG eXIf chunks or standalone TIFF files
+pub(super) fn parse(data: &[u8], m: &mut Metadata, with_dimensions: bool) -> Result<()> {
+    let tiff = Tiff::new(data)?;
+    let ifd0_offset = tiff.u32(4).context("no IFD0 offset")? as usize;
+
+    let mut exif_offset = None;
+    let mut gps_offset = None;
+
+    for entry in tiff.ifd(ifd0_offset) {
+        match entry.tag {
+            IMAGE_WIDTH if with_dimensions => set_if_none(&mut m.width, tiff.unsigned(&entry)),
+            IMAGE_HEIGHT if with_dimensions => set_if_none(&mut m.height, tiff.unsigned(&entry)),
+            MAKE => set_if_none(&mut m.make, tiff.ascii(&entry)),
+            MODEL => set_if_none(&mut m.model, tiff.ascii(&entry)),
+            ORIENTATION => set_if_none(&mut m.orientation, tiff.unsigned(&entry)),
+            EXIF_IFD => exif_offset = tiff.unsigned(&entry),
+            GPS_IFD => gps_offset = tiff.unsigned(&entry),
+            _ => (),
+        }
+    }
+
+    if let Some(offset) = exif_offset {
+        for entry in tiff.ifd(offset as usize) {
+            match entry.tag {
+                DATE_TIME_ORIGINAL => {
+                    set_if_none(&mut m.date, tiff.ascii(&entry).as_deref().and_then(parse_date))
+                }
+                OFFSET_TIME_ORIGINAL => set_if_none(
+                    &mut m.offset,
+                    tiff.ascii(&entry).as_deref().and_then(parse_offset),
+                ),
+                _ => (),
+            }
+        }
+    }
+
+    if let Some(offset) = gps_offset {
+        let entries = tiff.ifd(offset as usize);
+        let reference = entries
+            .iter()
+            .find(|e| e.tag == GPS_LATITUDE_REF)
+            .and_then(|e| tiff.ascii(e));
+        let lat = entries
+            .iter()
+            .find(|e| e.tag == GPS_LATITUDE)
+            .and_then(|e| tiff.rationals(e))
+            .and_then(|dms| latitude(&dms, reference));
+        set_if_none(&mut m.lat, lat);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    fn entry(tag: u16, kind: u16, count: u32, value: u32) -> Vec<u8> {
+        let mut v = vec![];
+        v.extend(tag.to_le_bytes());
+        v.extend(kind.to_le_bytes());
+        v.extend(count.to_le_bytes());
+        v.extend(value.to_le_bytes());
+        v
+    }
+
+    /// Little-endian TIFF with make, orientation, date and a southern latitude
+    pub fn sample() -> Vec<u8> {
+        let mut data = b"II".to_vec();
+        data.extend(42u16.to_le_bytes());
+        data.extend(8u32.to_le_bytes());
+
+        // IFD0 at 8 with 4 entries: 2 + 4 * 12 + 4 = 54 bytes
+        let make_offset = 62u32;
+        let exif_offset = 70u32;
+        let gps_offset = 108u32;
+        data.extend(4u16.to_le_bytes());
+        data.extend(entry(MAKE, 2, 8, make_offset));
+        data.extend(entry(ORIENTATION, 3, 1, 6));
+        data.extend(entry(EXIF_IFD, 4, 1, exif_offset));
+        data.extend(entry(GPS_IFD, 4, 1, gps_offset));
+        data.extend(0u32.to_le_bytes());
+        assert_eq!(data.len(), make_offset as usize);
+        data.extend(b"samsung\0");
+
+        // Exif IFD at 70 with 1 entry, value at 88
+        assert_eq!(data.len(), exif_offset as usize);
+        data.extend(1u16.to_le_bytes());
+        data.extend(entry(DATE_TIME_ORIGINAL, 2, 20, 88));
+        data.extend(0u32.to_le_bytes());
+        data.extend(b"2022:10:13 05:23:05\0");
+
+        // GPS IFD at 108 with 2 entries, rationals at 138
+        assert_eq!(data.len(), gps_offset as usize);
+        data.extend(2u16.to_le_bytes());
+        data.extend(entry(GPS_LATITUDE_REF, 2, 2, u32::from_le_bytes(*b"S\0\0\0")));
+        data.extend(entry(GPS_LATITUDE, 5, 3, 138));
+        data.extend(0u32.to_le_bytes());
+        for (n, d) in [(10u32, 1u32), (30, 1), (0, 1)] {
+            data.extend(n.to_le_bytes());
+            data.extend(d.to_le_bytes());
+        }
+
+        data
+    }
+
+    #[test]
+    fn test_parse() {
+        let mut m = Metadata::default();
+        parse(&sample(), &mut m, true).unwrap();
+
+        assert_eq!(m.make.as_deref(), Some("samsung"));
+        assert_eq!(m.orientation, Some(6));
+        assert_eq!(m.lat, Some(-10.5));
+        assert_eq!(m.date, parse_date("2022:10:13 05:23:05"));
+        assert_eq!(m.width, None);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let mut m = Metadata::default();
+        assert!(parse(b"XX*\0", &mut m, true).is_err());
+        assert!(parse(b"II\0\0", &mut m, true).is_err());
+    }
+}
@@ -0,0 +1,95 @@
+use super::{non_empty, parse_date, parse_offset, set_if_none, Metadata};
+
+/// Looks up a property regardless of its namespace prefix, either as an
+/// attribute or as a simple element
+fn value(xmp: &str, name: &str) -> Option<String> {
+    let lower = xmp.to_ascii_lowercase();
+    let needle = format!(":{}", name.to_ascii_lowercase());
+
+    let mut from = 0;
+    while let Some(i) = lower[from..].find(&needle) {
+        let start = from + i + needle.len();
+        let rest = &xmp[start..];
+        if let Some(r) = rest.strip_prefix("=\"") {
+            return r.split('"').next().and_then(non_empty);
+        }
+        if let Some(r) = rest.strip_prefix("='") {
+            return r.split('\'').next().and_then(non_empty);
+        }
+        if let Some(r) = rest.strip_prefix('>') {
+            return r.split('<').next().and_then(non_empty);
+        }
+        from = start;
+    }
+
+    None
+}
+
+/// Converts "10,30.0N" or "10.5" to decimal degrees
+fn latitude(raw: &str) -> Option<f32> {
+    let (raw, sign) = match raw.chars().last()? {
+        'N' | 'n' => (&raw[..raw.len() - 1], 1.0),
+        'S' | 's' => (&raw[..raw.len() - 1], -1.0),
+        _ => (raw, 1.0),
+    };
+
+    let mut lat = 0.0;
+    for (part, divisor) in raw.split(',').zip([1.0, 60.0, 3600.0]) {
+        lat += part.trim().parse::<f64>().ok()? / divisor;
+    }
+
+    Some((sign * lat) as f32)
+}
+
+/// Fills whatever the EXIF data didn't provide
+pub(super) fn parse(xmp: &str, m: &mut Metadata) {
+    set_if_none(&mut m.make, value(xmp, "Make"));
+    set_if_none(&mut m.model, value(xmp, "Model"));
+    set_if_none(&mut m.android_make, value(xmp, "AndroidManufacturer"));
+    set_if_none(&mut m.android_model, value(xmp, "AndroidModel"));
+    set_if_none(
+        &mut m.orientation,
+        value(xmp, "Orientation").and_then(|o| o.parse().ok()),
+    );
+    set_if_none(
+        &mut m.lat,
+        value(xmp, "GPSLatitude").as_deref().and_then(latitude),
+    );
+
+    if let Some(date) = value(xmp, "DateTimeOriginal") {
+        set_if_none(&mut m.date, parse_date(&date));
+        set_if_none(&mut m.offset, date.get(19..).and_then(parse_offset));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let xmp = r#"<rdf:Description rdf:about=''
+          xmlns:exif='http://ns.adobe.com/exif/1.0/'>
+          <exif:GPSLatitude>10,30.0N</exif:GPSLatitude>
+          <exif:DateTimeOriginal>2022-10-13T05:23:05-03:00</exif:DateTimeOriginal>
+         </rdf:Description>
+         <rdf:Description xmp:AndroidModel='MYCAMERA' tiff:Make="">"#;
+
+        let mut m = Metadata::default();
+        parse(xmp, &mut m);
+
+        assert_eq!(m.lat, Some(10.5));
+        assert_eq!(m.android_model.as_deref(), Some("MYCAMERA"));
+        assert_eq!(m.make, None);
+        assert_eq!(m.date, parse_date("2022:10:13 05:23:05"));
+        assert_eq!(m.offset, parse_offset("-03:00"));
+    }
+
+    #[test]
+    fn test_latitude() {
+        assert_eq!(latitude("10,30.0S"), Some(-10.5));
+        assert_eq!(latitude("10,30,36N"), Some(10.51));
+        assert_eq!(latitude("-84.68"), Some(-84.68));
+        assert_eq!(latitude("foo"), None);
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::{self, yaml::Sink};
+use crate::{log, plan, telegram};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fmt;
+use std::io::Write;
+use std::process::{Command, Stdio};
+use std::time::Duration;
+
+/// What gets reported to the configured sinks while files are processed
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event {
+    RunStarted {
+        label: String,
+        files: usize,
+    },
+    FileFailed {
+        label: String,
+        file: usize,
+        error: String,
+    },
+    RunSummary {
+        label: String,
+        files: usize,
+        succeeded: usize,
+        failed: usize,
+        error: Option<String>,
+    },
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Event::RunStarted { label, files } => write!(f, "storm {}: started, {} files", label, files),
+            Event::FileFailed { label, file, error } => {
+                write!(f, "storm {}: file {} failed: {}", label, file, error)
+            }
+            Event::RunSummary {
+                label,
+                files,
+                succeeded,
+                failed,
+                error,
+            } => {
+                write!(
+                    f,
+                    "storm {}: {} of {} files succeeded, {} failed",
+                    label, succeeded, files, failed
+                )?;
+                if let Some(error) = error {
+                    write!(f, "\n{}", error)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Failing sinks are only logged, so that they never fail the run itself
+pub fn send(event: &Event) {
+    if plan::is_dry_run() {
+        return;
+    }
+
+    for sink in &config::get().yaml.notifications {
+        if let Err(e) = deliver(sink, event) {
+            log::error(&format!("Failed to notify: {:#}", e));
+        }
+    }
+}
+
+fn deliver(sink: &Sink, event: &Event) -> Result<()> {
+    match sink {
+        Sink::Telegram => telegram::send_message(&event.to_string()),
+        Sink::Webhook { url } => webhook(url, &serde_json::to_string(event)?),
+        Sink::Command { cmd } => command(cmd, &serde_json::to_string(event)?),
+    }
+}
+
+fn webhook(url: &str, json: &str) -> Result<()> {
+    let result = ureq::post(url)
+        .timeout(Duration::from_secs(30))
+        .set("Content-Type", "application/json")
+        .send_string(json);
+
+    // the error message would include the url, which often has a secret in it
+    match result {
+        Ok(_) => Ok(()),
+        Err(ureq::Error::Status(code, _)) => Err(anyhow!("webhook answered {}", code)),
+        Err(ureq::Error::Transport(t)) => Err(anyhow!("webhook unreachable: {}", t.kind())),
+    }
+}
+
+/// Runs the command with `sh -c`, with the event as a JSON line on stdin
+fn command(cmd: &str, json: &str) -> Result<()> {
+    let mut child = Command::new("sh")
+        .args(["-c", cmd])
+        .stdin(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("unable to run {}", cmd))?;
+
+    let mut stdin = child.stdin.take().context("no stdin")?;
+    stdin.write_all(format!("{}\n", json).as_bytes())?;
+    drop(stdin);
+
+    let status = child.wait()?;
+    if !status.success() {
+        return Err(anyhow!("{} exited with {}", cmd, status));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_events() -> Result<()> {
+        let summary = Event::RunSummary {
+            label: "cs".into(),
+            files: 3,
+            succeeded: 2,
+            failed: 1,
+            error: Some("1 files failed".into()),
+        };
+        assert_eq!(
+            serde_json::to_string(&summary)?,
+            r#"{"event":"run_summary","label":"cs","files":3,"succeeded":2,"failed":1,"error":"1 files failed"}"#
+        );
+        assert_eq!(
+            summary.to_string(),
+            "storm cs: 2 of 3 files succeeded, 1 failed\n1 files failed"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_command() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let out = dir.path().join("event.json");
+
+        command(
+            &format!("cat > '{}'", out.display()),
+            r#"{"event":"run_started"}"#,
+        )?;
+        assert_eq!(std::fs::read_to_string(&out)?, "{\"event\":\"run_started\"}\n");
+
+        assert!(command("exit 3", "{}").is_err());
+
+        Ok(())
+    }
+}
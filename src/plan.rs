@@ -0,0 +1,171 @@
+use crate::fs::IPathBuf;
+use anyhow::Result;
+use once_cell::sync::OnceCell;
+use serde::Serialize;
+use std::fmt::{self, Debug, Display};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, MutexGuard};
+
+#[derive(Debug, Serialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum Action {
+    Move {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Copy {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    RemoveFile {
+        path: PathBuf,
+    },
+    RemoveDir {
+        path: PathBuf,
+    },
+    Write {
+        path: PathBuf,
+    },
+    Zip {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    Compress {
+        from: PathBuf,
+        to: PathBuf,
+        quality: String,
+    },
+    Normalize {
+        path: PathBuf,
+    },
+    Push {
+        from: PathBuf,
+        remote: String,
+    },
+    Pull {
+        remote: String,
+        files: Vec<PathBuf>,
+        to: PathBuf,
+    },
+    RemoteMove {
+        remote: String,
+        to: PathBuf,
+    },
+    RemoteRmdirs {
+        remote: String,
+    },
+    RemoteDelete {
+        remote: String,
+    },
+    Upload {
+        path: PathBuf,
+        provider: String,
+    },
+    Forward {
+        path: PathBuf,
+        chat_id: String,
+    },
+    Album {
+        folder: PathBuf,
+        files: usize,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct Decision {
+    path: PathBuf,
+    reason: String,
+}
+
+#[derive(Default, Serialize)]
+struct Plan {
+    decisions: Vec<Decision>,
+    actions: Vec<Action>,
+}
+
+static INSTANCE: OnceCell<Mutex<Plan>> = OnceCell::new();
+
+fn get() -> Option<MutexGuard<'static, Plan>> {
+    INSTANCE.get().map(|m| m.lock().expect("failed to lock plan"))
+}
+
+pub fn setup() -> Result<()> {
+    INSTANCE
+        .set(Mutex::new(Plan::default()))
+        .map_err(|_| anyhow!("unable to set plan"))?;
+    Ok(())
+}
+
+pub fn is_dry_run() -> bool {
+    INSTANCE.get().is_some()
+}
+
+/// Records the action when in dry-run mode, in which case the caller must not perform it
+pub fn skip(action: Action) -> bool {
+    match get() {
+        Some(mut plan) => {
+            plan.actions.push(action);
+            true
+        }
+        None => false,
+    }
+}
+
+pub fn decision<R: Debug>(path: &Path, reason: &R) {
+    if let Some(mut plan) = get() {
+        plan.decisions.push(Decision {
+            path: path.into(),
+            reason: format!("{:?}", reason),
+        });
+    }
+}
+
+pub fn print(json: bool) -> Result<()> {
+    let plan = match get() {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&*plan)?);
+        return Ok(());
+    }
+
+    for d in &plan.decisions {
+        println!("{}\t{}", d.reason, d.path.to_string());
+    }
+    for a in &plan.actions {
+        println!("{}", a);
+    }
+
+    Ok(())
+}
+
+impl Display for Action {
+    fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
+        use Action::*;
+
+        let txt = match self {
+            Move { from, to } => format!("mv {} {}", from.to_string(), to.to_string()),
+            Copy { from, to } => format!("cp {} {}", from.to_string(), to.to_string()),
+            RemoveFile { path } => format!("rm {}", path.to_string()),
+            RemoveDir { path } => format!("rm -r {}", path.to_string()),
+            Write { path } => format!("write {}", path.to_string()),
+            Zip { from, to } => format!("zip {} {}", from.to_string(), to.to_string()),
+            Compress { from, to, quality } => {
+                format!("compress {} {} {}", quality, from.to_string(), to.to_string())
+            }
+            Normalize { path } => format!("normalize {}", path.to_string()),
+            Push { from, remote } => format!("push {} {}", from.to_string(), remote),
+            Pull { remote, files, to } => format!("pull {} {} files {}", remote, files.len(), to.to_string()),
+            RemoteMove { remote, to } => format!("move {} {}", remote, to.to_string()),
+            RemoteRmdirs { remote } => format!("rmdirs {}", remote),
+            RemoteDelete { remote } => format!("deletefile {}", remote),
+            Upload { path, provider } => format!("upload {} {}", provider, path.to_string()),
+            Forward { path, chat_id } => format!("forward {} {}", chat_id, path.to_string()),
+            Album { folder, files } => format!("album {} files {}", files, folder.to_string()),
+        };
+
+        fmt.write_str(&txt)
+    }
+}
@@ -0,0 +1,161 @@
+use super::{Backend, Entry};
+use crate::fs::{self, IPathBuf};
+use crate::plan::{self, Action};
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// A folder on this machine standing in for a remote, mostly for testing
+/// the whole pipeline offline
+pub(super) struct Local {
+    pub root: PathBuf,
+}
+
+impl Local {
+    fn path(&self, remote: &Path) -> PathBuf {
+        self.root.join(remote.strip_prefix("/").unwrap_or(remote))
+    }
+}
+
+/// Files under a folder, with paths relative to it
+fn files(folder: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = vec![];
+    for entry in WalkDir::new(folder).sort_by_file_name() {
+        let entry = entry?;
+        if entry.file_type().is_file() && !fs::is_os_file(&entry) {
+            files.push(entry.path().strip_prefix(folder)?.to_owned());
+        }
+    }
+    Ok(files)
+}
+
+impl Backend for Local {
+    fn put(&self, local: &Path, remote: &Path) -> Result<()> {
+        let to = self.path(remote);
+        if !local.is_dir() {
+            // so that an interrupted copy never replaces the file
+            let partial = PathBuf::from(format!("{}.storm_part", to.to_string()));
+            fs::copy(local, &partial)?;
+            return fs::replace(&partial, &to);
+        }
+
+        for file in files(local)? {
+            fs::copy(&local.join(&file), &to.join(&file))?;
+        }
+        Ok(())
+    }
+
+    fn get(&self, remote: &Path, local: &Path) -> Result<()> {
+        let from = self.path(remote);
+        fs::create_parent_all(local)?;
+        std::fs::copy(&from, local).with_context(|| format!("Failed to get {}", from.to_string()))?;
+        Ok(())
+    }
+
+    fn get_many(&self, remotes: &[PathBuf], local: &Path) -> Result<()> {
+        if plan::skip(Action::Pull {
+            remote: self.root.to_string(),
+            files: remotes.to_vec(),
+            to: local.into(),
+        }) {
+            return Ok(());
+        }
+
+        for remote in remotes {
+            self.get(remote, &local.join(remote.strip_prefix("/").unwrap_or(remote)))?;
+        }
+        Ok(())
+    }
+
+    fn list(&self, remote: &Path) -> Result<Vec<Entry>> {
+        let folder = self.path(remote);
+        if !folder.exists() {
+            return Err(anyhow!("{} not found", folder.to_string()));
+        }
+
+        let mut entries = vec![];
+        for path in files(&folder)? {
+            let full = folder.join(&path);
+            let metadata = std::fs::metadata(&full)?;
+            let mut hashes = HashMap::new();
+            hashes.insert("md5".to_owned(), fs::md5(&full)?);
+            entries.push(Entry {
+                path,
+                size: metadata.len() as i64,
+                mod_time: DateTime::<Utc>::from(metadata.modified()?).to_rfc3339(),
+                is_dir: false,
+                hashes,
+            });
+        }
+        Ok(entries)
+    }
+
+    fn exists(&self, remote: &Path) -> Result<bool> {
+        Ok(self.path(remote).is_file())
+    }
+
+    fn delete(&self, remote: &Path) -> Result<()> {
+        fs::remove_file(&self.path(remote))
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        fs::replace(&self.path(from), &self.path(to))
+    }
+
+    fn mv(&self, remote: &Path, local: &Path) -> Result<()> {
+        let from = self.path(remote);
+        for file in files(&from)? {
+            fs::mv(&from.join(&file), &local.join(&file))?;
+        }
+        fs::remove_dir_all(&from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_local() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let local = dir.path().join("local");
+        std::fs::create_dir_all(local.join("books"))?;
+        std::fs::write(local.join("books/alien.txt"), "alien")?;
+        std::fs::write(local.join("monster.txt"), "monster")?;
+
+        let backend = Local {
+            root: dir.path().join("remote"),
+        };
+        backend.put(&local, Path::new("/shelf"))?;
+
+        let listed = backend.list(Path::new("/shelf"))?;
+        let paths = listed.iter().map(|e| e.path.to_string()).collect::<Vec<_>>();
+        assert_eq!(paths, vec!["books/alien.txt", "monster.txt"]);
+        assert_eq!(listed[0].size, 5);
+        assert_eq!(
+            listed[0].md5(),
+            Some(format!("{:x}", md5::compute("alien")).as_str())
+        );
+        assert!(listed[0].modified().is_some());
+
+        assert!(backend.exists(Path::new("/shelf/monster.txt"))?);
+        backend.delete(Path::new("/shelf/monster.txt"))?;
+        assert!(!backend.exists(Path::new("/shelf/monster.txt"))?);
+
+        let fetched = dir.path().join("fetched");
+        backend.get_many(&[PathBuf::from("/shelf/books/alien.txt")], &fetched)?;
+        assert_eq!(
+            std::fs::read_to_string(fetched.join("shelf/books/alien.txt"))?,
+            "alien"
+        );
+
+        let moved = dir.path().join("moved");
+        backend.mv(Path::new("/shelf"), &moved)?;
+        assert_eq!(std::fs::read_to_string(moved.join("books/alien.txt"))?, "alien");
+        assert!(!dir.path().join("remote/shelf").exists());
+
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+mod local;
+mod rclone;
+mod telegram;
+
+use crate::config;
+use crate::config::yaml::Provider;
+use crate::fs;
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+pub use crate::rclone::Entry;
+
+pub type ProviderId = str;
+
+/// Where a provider's files end up. Remote paths are absolute within the
+/// provider
+pub trait Backend {
+    /// Copies a local file to the remote path, or the contents of a local
+    /// folder under it
+    fn put(&self, local: &Path, remote: &Path) -> Result<()>;
+    fn get(&self, remote: &Path, local: &Path) -> Result<()>;
+    /// Every file under the remote path, with paths relative to it
+    fn list(&self, remote: &Path) -> Result<Vec<Entry>>;
+    fn exists(&self, remote: &Path) -> Result<bool>;
+    fn delete(&self, remote: &Path) -> Result<()>;
+    /// Moves the contents of a remote folder into a local one
+    fn mv(&self, remote: &Path, local: &Path) -> Result<()>;
+    /// Moves a remote file to another remote path, replacing what is there
+    fn rename(&self, from: &Path, to: &Path) -> Result<()>;
+
+    /// Downloads remote files into a local folder, keeping their paths
+    fn get_many(&self, remotes: &[PathBuf], local: &Path) -> Result<()> {
+        for remote in remotes {
+            self.get(remote, &local.join(remote.strip_prefix("/").unwrap_or(remote)))?;
+        }
+        Ok(())
+    }
+
+    /// Uploads the contents of a local folder and removes it
+    fn put_and_rm(&self, local: &Path, remote: &Path) -> Result<()> {
+        self.put(local, remote)?;
+        fs::remove_dir_all(local)
+    }
+}
+
+#[derive(Debug, Error)]
+pub enum ProviderError {
+    #[error("Provider not defined. Tried: {tried:?}, available: {available:?}")]
+    Undefined { tried: String, available: Vec<String> },
+}
+
+fn multiple_folder_path(provider: &Provider, relative: &Path) -> PathBuf {
+    provider.buffer.join(relative)
+}
+
+fn single_folder_path(provider: &Provider, relative: &Path) -> PathBuf {
+    let filename = relative
+        .iter()
+        .filter_map(|s| s.to_str())
+        .collect::<Vec<&str>>()
+        .join("_");
+    provider.buffer.join(filename)
+}
+
+pub fn get(provider_id: &ProviderId) -> Result<&'static Provider> {
+    config::get()
+        .yaml
+        .cloud
+        .providers
+        .get(provider_id)
+        .ok_or_else(|| {
+            let tried = provider_id.into();
+            let available = config::get()
+                .yaml
+                .cloud
+                .providers
+                .keys()
+                .map(|x| x.to_owned())
+                .collect();
+            ProviderError::Undefined { tried, available }
+        })
+        .map_err(|e| e.into())
+}
+
+pub fn backend(provider_id: &ProviderId) -> Result<Box<dyn Backend>> {
+    let provider = get(provider_id)?;
+    let default_kind = if provider_id == crate::telegram::PROVIDER_ID {
+        "telegram"
+    } else {
+        "rclone"
+    };
+
+    match provider.kind.as_deref().unwrap_or(default_kind) {
+        "rclone" => Ok(Box::new(rclone::Rclone {
+            provider_id: provider_id.into(),
+        })),
+        "local" => Ok(Box::new(local::Local {
+            root: provider.root.clone().context("local providers need a root")?,
+        })),
+        "telegram" => Ok(Box::new(telegram::Telegram)),
+        other => Err(anyhow!("unsupported provider type {}", other)),
+    }
+}
+
+/// A folder on this machine used like a remote
+pub fn local_backend(root: &Path) -> Box<dyn Backend> {
+    Box::new(local::Local { root: root.into() })
+}
+
+/// The remote path given, or the provider's fallback, or the root
+pub fn remote_path(provider_id: &ProviderId, remote_path: Option<PathBuf>) -> Result<PathBuf> {
+    let provider = get(provider_id)?;
+    Ok(remote_path
+        .or_else(|| provider.remote_path_fallback.clone())
+        .unwrap_or_else(|| PathBuf::from("/")))
+}
+
+pub fn path(provider_id: &ProviderId, relative: &Path) -> Result<PathBuf> {
+    let provider = get(provider_id)?;
+
+    let path = if provider.single_folder {
+        single_folder_path(provider, relative)
+    } else {
+        multiple_folder_path(provider, relative)
+    };
+
+    Ok(path)
+}
@@ -0,0 +1,69 @@
+use super::{Backend, Entry};
+use crate::fs::IPathBuf;
+use crate::log;
+use crate::provider::ProviderId;
+use crate::rclone;
+use anyhow::Result;
+use std::path::{Path, PathBuf};
+
+pub(super) struct Rclone {
+    pub provider_id: String,
+}
+
+impl Rclone {
+    fn id(&self) -> &ProviderId {
+        &self.provider_id
+    }
+}
+
+impl Backend for Rclone {
+    fn put(&self, local: &Path, remote: &Path) -> Result<()> {
+        if local.is_dir() {
+            rclone::push(local, self.id(), Some(remote.into()))?;
+        } else {
+            rclone::push_one(local, self.id(), remote)?;
+        }
+        log::debug(&format!("{} pushed to {}", local.to_string(), remote.to_string()));
+        Ok(())
+    }
+
+    fn get(&self, remote: &Path, local: &Path) -> Result<()> {
+        rclone::pull_one(self.id(), remote, local)?;
+        Ok(())
+    }
+
+    fn get_many(&self, remotes: &[PathBuf], local: &Path) -> Result<()> {
+        rclone::pull_many(self.id(), remotes, local)?;
+        Ok(())
+    }
+
+    fn list(&self, remote: &Path) -> Result<Vec<Entry>> {
+        rclone::lsjson(self.id(), Some(remote.into()), rclone::ListOptions::FULL)
+    }
+
+    fn exists(&self, remote: &Path) -> Result<bool> {
+        rclone::exists(self.id(), remote)
+    }
+
+    fn delete(&self, remote: &Path) -> Result<()> {
+        rclone::deletefile(self.id(), remote)?;
+        Ok(())
+    }
+
+    fn rename(&self, from: &Path, to: &Path) -> Result<()> {
+        rclone::moveto(self.id(), from, to)?;
+        Ok(())
+    }
+
+    fn mv(&self, remote: &Path, local: &Path) -> Result<()> {
+        rclone::mv(self.id(), remote, local)?;
+        if let Err(e) = rclone::rmdirs(self.id(), remote) {
+            log::warn(&format!(
+                "Unable to remove empty folders of {}: {:#}",
+                remote.to_string(),
+                e
+            ));
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,58 @@
+use super::{Backend, Entry};
+use crate::telegram;
+use anyhow::Result;
+use std::path::Path;
+
+/// Uploads only, as documents posted to the configured chat
+pub(super) struct Telegram;
+
+fn check_root(remote: &Path) -> Result<()> {
+    if remote != Path::new("/") {
+        return Err(anyhow!("No support for remote_path with Telegram"));
+    }
+    Ok(())
+}
+
+fn unsupported<T>(operation: &str) -> Result<T> {
+    Err(anyhow!("Telegram doesn't support {}", operation))
+}
+
+impl Backend for Telegram {
+    fn put(&self, local: &Path, remote: &Path) -> Result<()> {
+        check_root(remote)?;
+        if local.is_dir() {
+            telegram::upload_folder(local)
+        } else {
+            telegram::upload(local)
+        }
+    }
+
+    fn get(&self, _remote: &Path, _local: &Path) -> Result<()> {
+        unsupported("get")
+    }
+
+    fn list(&self, _remote: &Path) -> Result<Vec<Entry>> {
+        unsupported("list")
+    }
+
+    fn exists(&self, _remote: &Path) -> Result<bool> {
+        unsupported("exists")
+    }
+
+    fn delete(&self, _remote: &Path) -> Result<()> {
+        unsupported("delete")
+    }
+
+    fn mv(&self, _remote: &Path, _local: &Path) -> Result<()> {
+        unsupported("mv")
+    }
+
+    fn rename(&self, _from: &Path, _to: &Path) -> Result<()> {
+        unsupported("rename")
+    }
+
+    /// Uploaded files are removed one by one, so whatever couldn't be uploaded stays
+    fn put_and_rm(&self, local: &Path, remote: &Path) -> Result<()> {
+        self.put(local, remote)
+    }
+}
@@ -1,12 +1,64 @@
 use crate::fs;
 use crate::fs::IPathBuf;
+use crate::plan::{self, Action};
 use crate::provider;
 use crate::provider::ProviderId;
-use crate::shell::{self, ShellCmd};
+use crate::shell::{self, ShellCmd, ShellError};
 use anyhow::{Context, Result};
+use chrono::{DateTime, NaiveDateTime};
+use serde::Deserialize;
+use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use tempfile::tempdir;
 
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+pub struct Entry {
+    pub path: PathBuf,
+    pub size: i64,
+    pub mod_time: String,
+    pub is_dir: bool,
+    /// Keyed by lowercase hash name, empty for backends without hashes
+    #[serde(default)]
+    pub hashes: HashMap<String, String>,
+}
+
+impl Entry {
+    /// In UTC, like dates read from the local filesystem
+    pub fn modified(&self) -> Option<NaiveDateTime> {
+        DateTime::parse_from_rfc3339(&self.mod_time)
+            .ok()
+            .map(|d| d.naive_utc())
+    }
+
+    pub fn md5(&self) -> Option<&str> {
+        self.hashes
+            .get("md5")
+            .map(String::as_str)
+            .filter(|h| !h.is_empty())
+    }
+}
+
+/// How far `lsjson` goes, hashing every object being the expensive part on
+/// some remotes
+#[derive(Debug, Clone, Copy)]
+pub struct ListOptions {
+    pub recursive: bool,
+    pub hashes: bool,
+}
+
+impl ListOptions {
+    pub const FULL: Self = Self {
+        recursive: true,
+        hashes: true,
+    };
+    /// Only the files right under the remote path, without hashes
+    pub const SHALLOW: Self = Self {
+        recursive: false,
+        hashes: false,
+    };
+}
+
 fn get_rclone_id(provider_id: &ProviderId) -> Result<&String> {
     provider::get(provider_id)?
         .rclone
@@ -14,11 +66,27 @@ fn get_rclone_id(provider_id: &ProviderId) -> Result<&String> {
         .context("rclone not supported")
 }
 
+fn planned(args: &[&str]) -> ShellCmd {
+    ShellCmd {
+        program: "rclone".into(),
+        args: args.iter().map(|x| x.to_string()).collect(),
+        res: None,
+    }
+}
+
 pub fn pull_many(provider_id: &ProviderId, remote_paths: &[PathBuf], local: &Path) -> Result<ShellCmd> {
     let rclone_id = get_rclone_id(provider_id)?;
 
     let remote_str = format!("{}:/", rclone_id);
 
+    if plan::skip(Action::Pull {
+        remote: remote_str.clone(),
+        files: remote_paths.to_vec(),
+        to: local.into(),
+    }) {
+        return Ok(planned(&["copy", &remote_str, &local.to_string()]));
+    }
+
     let list = remote_paths
         .iter()
         .map(|p| p.to_string())
@@ -63,47 +131,88 @@ pub fn push(local: &Path, provider_id: &ProviderId, remote_path: Option<PathBuf>
     let mut args = vec!["--exclude", ".DS_Store", "-vv", "copy", &local_str, &remote_str];
     args.append(&mut extra.iter().map(String::as_str).collect());
 
+    if plan::skip(Action::Push {
+        from: local.into(),
+        remote: remote_str.clone(),
+    }) {
+        return Ok(planned(&args));
+    }
+
     shell::out_inherited("rclone", &args).map_err(|e| e.into())
 }
 
-pub fn ls(provider_id: &ProviderId, remote_path: Option<PathBuf>) -> Result<ShellCmd> {
+pub fn pull_one(provider_id: &ProviderId, remote_path: &Path, local: &Path) -> Result<ShellCmd> {
     let rclone_id = get_rclone_id(provider_id)?;
+    let remote_str = format!("{}:{}", rclone_id, remote_path.to_string());
 
-    let remote_str = format!(
-        "{}:{}",
-        rclone_id,
-        remote_path.map(|p| p.to_string()).unwrap_or_else(|| "/".into())
-    );
+    let args = &["copyto", &remote_str, &local.to_string()];
+    shell::out("rclone", args).map_err(|e| e.into())
+}
+
+pub fn push_one(local: &Path, provider_id: &ProviderId, remote_path: &Path) -> Result<ShellCmd> {
+    let rclone_id = get_rclone_id(provider_id)?;
+    let remote_str = format!("{}:{}", rclone_id, remote_path.to_string());
+
+    let args = &["copyto", &local.to_string(), &remote_str];
+
+    if plan::skip(Action::Push {
+        from: local.into(),
+        remote: remote_str.clone(),
+    }) {
+        return Ok(planned(args));
+    }
 
-    let args = &["ls", &remote_str];
     shell::out("rclone", args).map_err(|e| e.into())
 }
 
-pub fn has_prefixed_files(
+/// Files under the remote path, with paths relative to it
+pub fn lsjson(
     provider_id: &ProviderId,
     remote_path: Option<PathBuf>,
-    prefix: &str,
-) -> Result<bool> {
-    let provider = provider::get(provider_id)?;
-    let rclone_id = provider.rclone.as_ref().context("rclone not supported")?;
+    options: ListOptions,
+) -> Result<Vec<Entry>> {
+    let rclone_id = get_rclone_id(provider_id)?;
 
     let remote_str = format!(
         "{}:{}",
         rclone_id,
-        remote_path
-            .or_else(|| provider.remote_path_fallback.clone())
-            .map(|p| p.to_string())
-            .unwrap_or_else(|| "/".into())
+        remote_path.map(|p| p.to_string()).unwrap_or_else(|| "/".into())
     );
 
-    let include_str = format!("{}_*", prefix);
-
-    let args = &["lsf", &remote_str, "--include", &include_str];
-    let out = shell::out("rclone", args)?;
+    let mut args = vec!["lsjson", "--files-only"];
+    if options.recursive {
+        args.push("--recursive");
+    }
+    if options.hashes {
+        args.push("--hash");
+    }
+    args.push(&remote_str);
+    let out = shell::out("rclone", &args)?;
     let stdout = out.res.context("no res")?.stdout;
-    let lines = stdout.lines().count();
 
-    Ok(lines > 0)
+    serde_json::from_str(&stdout).context("invalid rclone lsjson output")
+}
+
+/// Whether a file exists at the remote path
+pub fn exists(provider_id: &ProviderId, remote_path: &Path) -> Result<bool> {
+    let rclone_id = get_rclone_id(provider_id)?;
+    let remote_str = format!("{}:{}", rclone_id, remote_path.to_string());
+
+    let args = &["lsjson", "--stat", "--files-only", &remote_str];
+    match shell::out("rclone", args) {
+        Ok(_) => Ok(true),
+        // directory not found, file not found
+        Err(ShellError::NonZero { cmd })
+            if cmd
+                .res
+                .as_ref()
+                .map(|r| r.code == 3 || r.code == 4)
+                .unwrap_or(false) =>
+        {
+            Ok(false)
+        }
+        Err(e) => Err(e.into()),
+    }
 }
 
 pub fn mv(provider_id: &ProviderId, remote_path: &Path, local_path: &Path) -> Result<ShellCmd> {
@@ -117,13 +226,61 @@ pub fn mv(provider_id: &ProviderId, remote_path: &Path, local_path: &Path) -> Re
         &remote_str,
         &local_path.to_string(),
     ];
+
+    if plan::skip(Action::RemoteMove {
+        remote: remote_str.clone(),
+        to: local_path.into(),
+    }) {
+        return Ok(planned(args));
+    }
+
     shell::out_inherited("rclone", args).map_err(|e| e.into())
 }
 
+/// Server-side, replacing the destination
+pub fn moveto(provider_id: &ProviderId, from: &Path, to: &Path) -> Result<ShellCmd> {
+    let rclone_id = get_rclone_id(provider_id)?;
+    let from_str = format!("{}:{}", rclone_id, from.to_string());
+    let to_str = format!("{}:{}", rclone_id, to.to_string());
+
+    let args = &["moveto", &from_str, &to_str];
+
+    if plan::skip(Action::RemoteMove {
+        remote: from_str.clone(),
+        to: PathBuf::from(&to_str),
+    }) {
+        return Ok(planned(args));
+    }
+
+    shell::out("rclone", args).map_err(|e| e.into())
+}
+
+pub fn deletefile(provider_id: &ProviderId, remote_path: &Path) -> Result<ShellCmd> {
+    let rclone_id = get_rclone_id(provider_id)?;
+    let remote_str = format!("{}:{}", rclone_id, remote_path.to_string());
+
+    let args = &["deletefile", &remote_str];
+
+    if plan::skip(Action::RemoteDelete {
+        remote: remote_str.clone(),
+    }) {
+        return Ok(planned(args));
+    }
+
+    shell::out("rclone", args).map_err(|e| e.into())
+}
+
 pub fn rmdirs(provider_id: &ProviderId, remote_path: &Path) -> Result<ShellCmd> {
     let rclone_id = get_rclone_id(provider_id)?;
     let remote_str = format!("{}:{}", rclone_id, remote_path.to_string());
 
     let args = &["rmdirs", &remote_str];
+
+    if plan::skip(Action::RemoteRmdirs {
+        remote: remote_str.clone(),
+    }) {
+        return Ok(planned(args));
+    }
+
     shell::out_inherited("rclone", args).map_err(|e| e.into())
 }
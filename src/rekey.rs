@@ -0,0 +1,619 @@
+use crate::archive;
+use crate::config;
+use crate::crypto::Scheme;
+use crate::db;
+use crate::fs::{self, IPathBuf};
+use crate::log;
+use crate::provider::{self, Backend, ProviderId};
+use crate::shell;
+use crate::zip;
+use anyhow::{Context, Error, Result};
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::channel;
+use std::sync::Arc;
+use walkdir::WalkDir;
+use workerpool::thunk::{Thunk, ThunkWorker};
+use workerpool::Pool;
+
+#[derive(Clone, Copy)]
+pub enum Source<'a> {
+    Provider(&'a ProviderId),
+    Local(&'a Path),
+}
+
+struct WorkerResult(usize, Option<Error>);
+
+/// Rekeyed objects are uploaded next to the old ones with this suffix, and
+/// only moved over them once they are all there
+const PENDING_SUFFIX: &str = ".storm_rekey";
+
+/// Archives and the objects they are stored as, either the `.7z` itself or
+/// its `.7z.001`, `.7z.002`... volumes, plus pending rekeyed ones
+type Archives = BTreeMap<PathBuf, Vec<PathBuf>>;
+
+struct Keys {
+    old: String,
+    new: String,
+    scheme: Scheme,
+}
+
+impl Keys {
+    fn from_config() -> Result<Self> {
+        Ok(Self {
+            old: config::get().previous_crypto_password()?.to_owned(),
+            new: config::get().crypto_password()?.to_owned(),
+            scheme: config::get().password_scheme()?,
+        })
+    }
+}
+
+/// What the catalog says about the stored archive
+struct Stored {
+    hash: String,
+    size: u64,
+    volumes: usize,
+}
+
+impl Stored {
+    fn of(zip_path: &Path, volumes: usize) -> Result<Self> {
+        Ok(Self {
+            hash: fs::md5(zip_path)?,
+            size: std::fs::metadata(zip_path)?.len(),
+            volumes,
+        })
+    }
+}
+
+fn pending_name(object: &Path) -> PathBuf {
+    PathBuf::from(format!("{}{}", object.to_string(), PENDING_SUFFIX))
+}
+
+/// `x.7z.001.storm_rekey` -> `x.7z.001`
+fn pending_of(object: &Path) -> Option<PathBuf> {
+    object.to_str()?.strip_suffix(PENDING_SUFFIX).map(PathBuf::from)
+}
+
+fn add_object(archives: &mut Archives, path: PathBuf) {
+    let object = pending_of(&path).unwrap_or_else(|| path.clone());
+    if object.extension().map(|e| e == "7z").unwrap_or(false) {
+        archives.entry(object).or_default().push(path);
+    } else if let Some((archive, _)) = zip::volume_of(&object) {
+        archives.entry(archive).or_default().push(path);
+    }
+}
+
+fn list(source: Source) -> Result<Archives> {
+    let mut archives = Archives::new();
+
+    match source {
+        Source::Provider(provider_id) => {
+            let root = provider::remote_path(provider_id, None)?;
+            for entry in provider::backend(provider_id)?.list(&root)? {
+                if !entry.is_dir {
+                    add_object(&mut archives, entry.path);
+                }
+            }
+        }
+        Source::Local(root) => {
+            // left behind by an interrupted run
+            let walker = WalkDir::new(root)
+                .into_iter()
+                .filter_entry(|e| !e.file_name().to_string_lossy().starts_with(".storm_rekey"));
+            for entry in walker.filter_map(|e| e.ok()) {
+                let is_file = entry.metadata().map(|m| m.is_file()).unwrap_or(false);
+                if is_file {
+                    add_object(&mut archives, entry.path().strip_prefix(root)?.to_owned());
+                }
+            }
+        }
+    }
+
+    for (archive, objects) in archives.iter_mut() {
+        objects.sort();
+        let current = objects.iter().filter(|o| pending_of(o).is_none()).count();
+        if current > 1 && objects.contains(archive) {
+            return Err(anyhow!("{} is stored both whole and split", archive.to_string()));
+        }
+    }
+    Ok(archives)
+}
+
+/// Where the archives are, with paths relative to it matching the catalogs
+fn store(source: Source) -> Result<(Box<dyn Backend>, PathBuf)> {
+    match source {
+        Source::Provider(provider_id) => Ok((
+            provider::backend(provider_id)?,
+            provider::remote_path(provider_id, None)?,
+        )),
+        Source::Local(root) => Ok((provider::local_backend(root), PathBuf::from("/"))),
+    }
+}
+
+fn default_checkpoint(source: Source) -> Result<PathBuf> {
+    let name = match source {
+        Source::Provider(provider_id) => format!("rekey_{}.txt", provider_id),
+        Source::Local(root) => format!("rekey_{:x}.txt", md5::compute(root.to_string())),
+    };
+    Ok(fs::default_config_pathbuf()?.with_file_name(name))
+}
+
+fn read_checkpoint(checkpoint: &Path) -> Result<HashSet<PathBuf>> {
+    if !checkpoint.exists() {
+        return Ok(HashSet::new());
+    }
+    let done = fs::read_lines(checkpoint)?
+        .filter(|l| !l.trim().is_empty())
+        .map(PathBuf::from)
+        .collect();
+    Ok(done)
+}
+
+fn same_files(expected: &Path, actual: &Path) -> Result<bool> {
+    let mut n = 0;
+
+    for entry in WalkDir::new(expected).into_iter().filter_map(|e| e.ok()) {
+        let is_file = entry.metadata().map(|m| m.is_file()).unwrap_or(false);
+        if !is_file {
+            continue;
+        }
+        let other = actual.join(entry.path().strip_prefix(expected)?);
+        if !other.exists() || fs::md5(entry.path())? != fs::md5(&other)? {
+            return Ok(false);
+        }
+        n += 1;
+    }
+
+    let m = WalkDir::new(actual)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.metadata().map(|m| m.is_file()).unwrap_or(false))
+        .count();
+
+    Ok(n == m)
+}
+
+/// Returns false if the archive already opens with the new password
+fn rekey_file(zip_path: &Path, workdir: &Path, keys: &Keys) -> Result<bool> {
+    let content = workdir.join("content");
+    match zip::detect_scheme(&keys.old, zip_path) {
+        Ok(old_scheme) => zip::extract_with(old_scheme, &keys.old, zip_path, &content)?,
+        Err(e) => {
+            if zip::test(keys.scheme, &keys.new, zip_path).is_ok() {
+                return Ok(false);
+            }
+            return Err(e);
+        }
+    };
+
+    let filename = zip_path.file_name().context("no filename")?;
+    let rekeyed = workdir.join(filename);
+    zip::create(keys.scheme, &keys.new, &content, &rekeyed)?;
+    shell::out("touch", &["-r", &zip_path.to_string(), &rekeyed.to_string()])?;
+
+    let verify = workdir.join("verify");
+    zip::extract_with(keys.scheme, &keys.new, &rekeyed, &verify)?;
+    if !same_files(&content, &verify)? {
+        return Err(anyhow!("round-trip mismatch for {}", zip_path.to_string()));
+    }
+
+    fs::replace(&rekeyed, zip_path)?;
+
+    Ok(true)
+}
+
+/// Also called when the archive was already rekeyed, in case a previous run
+/// stopped before its catalog was updated
+fn update_catalog(relative: &Path, stored: &Stored) -> Result<()> {
+    let catalog = match archive::catalog_path(relative) {
+        Ok(p) if p.exists() => p,
+        _ => return Ok(()),
+    };
+
+    let mut db = db::read(&catalog)?;
+    db.add_tag("hash".into(), stored.hash.clone());
+    db.add_tag(db::SIZE_TAG.into(), stored.size.to_string());
+    db.add_tag(
+        db::PASSWORD_SCHEME_TAG.into(),
+        config::get().password_scheme()?.to_string(),
+    );
+    if stored.volumes > 1 {
+        db.add_tag(db::VOLUMES_TAG.into(), stored.volumes.to_string());
+    } else {
+        db.remove_tag(db::VOLUMES_TAG);
+    }
+    db::write(db, &catalog)
+}
+
+/// Downloads the objects into the folder as a single archive named after
+/// `relative`, returning it and the size of the first object
+fn download(
+    backend: &dyn Backend,
+    root: &Path,
+    relative: &Path,
+    objects: &[PathBuf],
+    folder: &Path,
+) -> Result<(PathBuf, u64)> {
+    let zip_path = folder.join(relative.file_name().context("no filename")?);
+    let parts = folder.join("parts");
+    let locals = objects
+        .iter()
+        .map(|o| Ok(parts.join(o.file_name().context("no filename")?)))
+        .collect::<Result<Vec<_>>>()?;
+    for (object, local) in objects.iter().zip(&locals) {
+        backend.get(&root.join(object), local)?;
+    }
+
+    let first_kb = std::fs::metadata(locals.first().context("no objects")?)?.len() / 1024;
+    if objects.len() == 1 && objects[0] == relative {
+        fs::replace(&locals[0], &zip_path)?;
+    } else {
+        zip::join(&locals, &zip_path)?;
+    }
+    fs::remove_dir_all(&parts)?;
+
+    Ok((zip_path, first_kb))
+}
+
+/// Checks that the uploaded objects have the size and, when the backend
+/// knows it, the md5 of the local files
+fn check_uploaded(backend: &dyn Backend, root: &Path, objects: &[PathBuf], locals: &[PathBuf]) -> Result<()> {
+    let folder = objects.first().and_then(|o| o.parent()).context("no parent")?;
+    let listed = backend.list(&root.join(folder))?;
+
+    for (object, local) in objects.iter().zip(locals) {
+        let filename = object.file_name().context("no filename")?;
+        let entry = listed
+            .iter()
+            .find(|e| e.path.as_os_str() == filename)
+            .with_context(|| format!("{} wasn't uploaded", object.to_string()))?;
+        let size_ok = entry.size as u64 == std::fs::metadata(local)?.len();
+        let md5_ok = match entry.md5() {
+            Some(md5) => md5 == fs::md5(local)?,
+            None => true,
+        };
+        if !size_ok || !md5_ok {
+            return Err(anyhow!("{} doesn't match what was uploaded", object.to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Moves the pending objects of the rekeyed archive over the old ones. Old
+/// objects that aren't replaced go first and pending ones are moved in order,
+/// so that an interrupted swap can be finished by `finish_swap`
+fn swap(backend: &dyn Backend, root: &Path, objects: &[PathBuf], rekeyed: &[PathBuf]) -> Result<()> {
+    let mut rekeyed = rekeyed.to_vec();
+    rekeyed.sort();
+    let targets = rekeyed
+        .iter()
+        .map(|r| pending_of(r).unwrap_or_else(|| r.clone()))
+        .collect::<Vec<_>>();
+
+    // the archive may now need fewer volumes
+    for stale in objects.iter().filter(|o| !targets.contains(o)) {
+        backend.delete(&root.join(stale))?;
+    }
+    for (from, to) in rekeyed.iter().zip(&targets).filter(|(from, to)| from != to) {
+        backend.rename(&root.join(from), &root.join(to))?;
+    }
+
+    Ok(())
+}
+
+/// Finishes the swap of a previous run if its pending objects, together with
+/// the volumes it already moved, open with the new password. Otherwise they
+/// were not all uploaded, so they are deleted and the archive starts over
+fn finish_swap(
+    backend: &dyn Backend,
+    root: &Path,
+    keys: &Keys,
+    relative: &Path,
+    objects: &[PathBuf],
+    pending: &[PathBuf],
+    folder: &Path,
+) -> Result<Option<Stored>> {
+    let targets = pending.iter().filter_map(|p| pending_of(p)).collect::<Vec<_>>();
+    let rekeyed = if targets.iter().any(|t| t == relative) {
+        vec![pending_name(relative)]
+    } else {
+        // volumes are moved in order, so the last one is still pending
+        let n = targets
+            .iter()
+            .filter_map(|t| zip::volume_of(t))
+            .map(|(_, i)| i)
+            .max();
+        (1..=n.unwrap_or(0))
+            .map(|i| {
+                let volume = zip::volume_path(relative, i);
+                if targets.contains(&volume) {
+                    pending_name(&volume)
+                } else {
+                    volume
+                }
+            })
+            .collect()
+    };
+
+    let opened = download(backend, root, relative, &rekeyed, folder)
+        .and_then(|(zip_path, _)| zip::test(keys.scheme, &keys.new, &zip_path).map(|_| zip_path));
+    match opened {
+        Ok(zip_path) => {
+            swap(backend, root, objects, &rekeyed)?;
+            let stored = Stored::of(&zip_path, rekeyed.len())?;
+            fs::remove_file(&zip_path)?;
+            Ok(Some(stored))
+        }
+        Err(e) => {
+            log::warn(&format!(
+                "Starting {} over, its rekey was interrupted: {:#}",
+                relative.to_string(),
+                e
+            ));
+            for p in pending {
+                backend.delete(&root.join(p))?;
+            }
+            Ok(None)
+        }
+    }
+}
+
+fn process(source: Source, keys: &Keys, relative: &Path, objects: &[PathBuf]) -> Result<Stored> {
+    let (backend, root) = store(source)?;
+    let backend = backend.as_ref();
+    let tmp = match source {
+        Source::Provider(_) => tempfile::tempdir()?,
+        // on the same disk as the archives, which can be big
+        Source::Local(root) => tempfile::Builder::new().prefix(".storm_rekey").tempdir_in(root)?,
+    };
+
+    let (pending, objects): (Vec<_>, Vec<_>) = objects.iter().cloned().partition(|o| pending_of(o).is_some());
+    if !pending.is_empty() {
+        let folder = tmp.path().join("pending");
+        if let Some(stored) = finish_swap(backend, &root, keys, relative, &objects, &pending, &folder)? {
+            return Ok(stored);
+        }
+    }
+
+    let (zip_path, first_kb) = download(backend, &root, relative, &objects, tmp.path())?;
+    let is_split = objects.len() > 1 || objects[0] != relative;
+
+    let rekeyed = rekey_file(&zip_path, &tmp.path().join("work"), keys)?;
+    let stored = Stored::of(&zip_path, objects.len())?;
+    if !rekeyed {
+        return Ok(stored);
+    }
+
+    let locals = if is_split {
+        let kb = match source {
+            Source::Provider(provider_id) => provider::get(provider_id)?.max_object_kb,
+            Source::Local(_) => None,
+        };
+        // the first volume is as big as any other
+        zip::split(&zip_path, kb.unwrap_or(first_kb as u32))?
+    } else {
+        vec![zip_path]
+    };
+
+    let folder = relative.parent().context("no parent")?;
+    let mut pending = vec![];
+    for local in &locals {
+        let object = pending_name(&folder.join(local.file_name().context("no filename")?));
+        backend.put(local, &root.join(&object))?;
+        pending.push(object);
+    }
+    check_uploaded(backend, &root, &pending, &locals)?;
+    swap(backend, &root, &objects, &pending)?;
+
+    Ok(Stored {
+        volumes: locals.len(),
+        ..stored
+    })
+}
+
+pub fn rekey(source: Source<'static>, checkpoint: Option<PathBuf>) -> Result<()> {
+    let checkpoint = match checkpoint {
+        Some(p) => p,
+        None => default_checkpoint(source)?,
+    };
+    let done = read_checkpoint(&checkpoint)?;
+
+    let archives = list(source)?
+        .into_iter()
+        .filter(|(p, _)| !done.contains(p))
+        .collect::<Vec<_>>();
+    let paths = archives.iter().map(|(p, _)| p.clone()).collect::<Vec<_>>();
+    let n = paths.len();
+    log::setup("rk".into(), n)?;
+    log::info(&format!(
+        "{} archives to rekey, {} already done according to {}",
+        n,
+        done.len(),
+        checkpoint.to_string()
+    ));
+
+    let keys = Arc::new(Keys::from_config()?);
+
+    let n_workers = config::get().yaml.parallelism.workers;
+    let pool: Pool<ThunkWorker<WorkerResult>> = Pool::new(n_workers as usize);
+
+    let (tx, rx) = channel();
+
+    for (i, (relative, objects)) in archives.into_iter().enumerate() {
+        let keys = keys.clone();
+        pool.execute_to(
+            tx.clone(),
+            Thunk::of(move || {
+                log::start(i);
+                let result = process(source, &keys, &relative, &objects)
+                    .and_then(|stored| update_catalog(&relative, &stored));
+                WorkerResult(i, result.err())
+            }),
+        );
+    }
+
+    let mut errors = 0;
+    for WorkerResult(i, error) in rx.iter().take(n) {
+        if let Some(e) = error {
+            log::failure(i, e);
+            errors += 1;
+        } else {
+            fs::append(&checkpoint, &format!("{}\n", paths[i].to_string()))?;
+            log::success(i);
+        }
+    }
+
+    if errors > 0 {
+        Err(anyhow!("{} files failed", errors))
+    } else {
+        fs::remove_file(&checkpoint)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn noise(len: usize) -> Vec<u8> {
+        let mut bytes = vec![];
+        let mut digest = md5::compute("seed").0;
+        while bytes.len() < len {
+            digest = md5::compute(digest).0;
+            bytes.extend_from_slice(&digest);
+        }
+        bytes.truncate(len);
+        bytes
+    }
+
+    fn opens(keys: &Keys, zip_path: &Path, content: &Path, workdir: &Path) -> Result<bool> {
+        zip::extract_with(keys.scheme, &keys.new, zip_path, workdir)?;
+        same_files(content, workdir)
+    }
+
+    #[test]
+    fn test_rekey_local() -> Result<()> {
+        config::setup_default();
+        let _ = shell::setup();
+
+        let dir = tempfile::tempdir()?;
+        let content = dir.path().join("content");
+        std::fs::create_dir_all(content.join("books"))?;
+        std::fs::write(content.join("books/alien.txt"), "alien")?;
+        std::fs::write(content.join("noise.bin"), noise(5000))?;
+
+        let root = dir.path().join("remote");
+        let folder = root.join("ByTimestamp");
+        zip::create(Scheme::Md5, "old", &content, &folder.join("whole.7z"))?;
+        zip::create(Scheme::Md5, "old", &content, &folder.join("split.7z"))?;
+        let volumes = zip::split(&folder.join("split.7z"), 2)?;
+        assert!(volumes.len() > 2);
+        // left behind by a rekey that stopped while uploading
+        std::fs::write(folder.join("whole.7z.storm_rekey"), "partial")?;
+
+        let keys = Keys {
+            old: "old".into(),
+            new: "new".into(),
+            scheme: Scheme::LATEST,
+        };
+        let source = Source::Local(&root);
+        let archives = list(source)?;
+        assert_eq!(archives.len(), 2);
+        for (relative, objects) in &archives {
+            let stored = process(source, &keys, relative, objects)?;
+            assert_eq!(
+                stored.volumes,
+                objects.iter().filter(|o| pending_of(o).is_none()).count()
+            );
+        }
+
+        let mut names = std::fs::read_dir(&folder)?
+            .map(|e| Ok(e?.file_name().to_string_lossy().into_owned()))
+            .collect::<Result<Vec<_>>>()?;
+        names.sort();
+        let mut expected = volumes
+            .iter()
+            .map(|v| v.file_name().unwrap().to_string_lossy().into_owned())
+            .collect::<Vec<_>>();
+        expected.push("whole.7z".into());
+        assert_eq!(names, expected);
+
+        assert!(opens(
+            &keys,
+            &folder.join("whole.7z"),
+            &content,
+            &dir.path().join("whole")
+        )?);
+        let joined = dir.path().join("split.7z");
+        zip::join(&volumes, &joined)?;
+        assert!(opens(&keys, &joined, &content, &dir.path().join("split"))?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_finish_swap() -> Result<()> {
+        config::setup_default();
+
+        let dir = tempfile::tempdir()?;
+        let content = dir.path().join("content");
+        std::fs::create_dir_all(&content)?;
+        std::fs::write(content.join("noise.bin"), noise(5000))?;
+
+        let keys = Keys {
+            old: "old".into(),
+            new: "new".into(),
+            scheme: Scheme::LATEST,
+        };
+        let root = dir.path().join("remote");
+        let folder = root.join("ByTimestamp");
+        let old = zip::split(
+            &{
+                let zip_path = dir.path().join("x.7z");
+                zip::create(Scheme::Md5, "old", &content, &zip_path)?;
+                zip_path
+            },
+            2,
+        )?;
+        let new = zip::split(
+            &{
+                let zip_path = dir.path().join("new/x.7z");
+                zip::create(keys.scheme, "new", &content, &zip_path)?;
+                zip_path
+            },
+            3,
+        )?;
+        assert!(old.len() > new.len());
+
+        // stopped after deleting the stale volumes and moving the first one
+        std::fs::create_dir_all(&folder)?;
+        for (i, volume) in new.iter().enumerate() {
+            let name = volume.file_name().unwrap().to_string_lossy().into_owned();
+            let name = if i == 0 {
+                name
+            } else {
+                format!("{}{}", name, PENDING_SUFFIX)
+            };
+            std::fs::copy(volume, folder.join(name))?;
+        }
+        for volume in &old[1..new.len()] {
+            std::fs::copy(volume, folder.join(volume.file_name().unwrap()))?;
+        }
+
+        let source = Source::Local(&root);
+        let archives = list(source)?;
+        let (relative, objects) = archives.iter().next().context("no archive")?;
+        let stored = process(source, &keys, relative, objects)?;
+        assert_eq!(stored.volumes, new.len());
+
+        let volumes = zip::volumes(&folder.join("x.7z"));
+        assert_eq!(volumes.len(), new.len());
+        assert_eq!(std::fs::read_dir(&folder)?.count(), new.len());
+        let joined = dir.path().join("joined/x.7z");
+        fs::create_parent_all(&joined)?;
+        zip::join(&volumes, &joined)?;
+        assert!(opens(&keys, &joined, &content, &dir.path().join("opened"))?);
+
+        Ok(())
+    }
+}
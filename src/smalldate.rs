@@ -1,5 +1,5 @@
 use anyhow::Result;
-use chrono::{Datelike, NaiveDate, NaiveDateTime, Utc};
+use chrono::{Datelike, Duration, NaiveDate, NaiveDateTime, Utc};
 use std::convert::TryFrom;
 use std::fmt::{self, Display, Formatter};
 use std::time::SystemTime;
@@ -10,7 +10,7 @@ use crate::crypto;
 #[derive(Error, Debug)]
 pub enum DateError {
     #[error("invalid seconds {0}")]
-    _Seconds(i64),
+    Seconds(i64),
     #[error("parse error {0}")]
     Parse(#[from] chrono::ParseError),
     #[error("invalid ymd {0}")]
@@ -32,6 +32,13 @@ pub fn now_hours_base36() -> Result<String> {
     Ok(az)
 }
 
+/// Same as `NaiveDateTime::from_timestamp_opt`, which newer chrono versions deprecate
+pub fn naive_from_timestamp(secs: i64) -> Option<NaiveDateTime> {
+    let epoch = NaiveDate::from_ymd_opt(1970, 1, 1)?.and_hms_opt(0, 0, 0)?;
+    let millis = secs.checked_mul(1000)?;
+    epoch.checked_add_signed(Duration::milliseconds(millis))
+}
+
 fn naive_to_date(naive: NaiveDateTime) -> Result<SmallDate> {
     let days = naive.num_days_from_ce() - DAYS_FROM_CE_TO_START;
     let days = u16::try_from(days)?;
@@ -53,10 +60,22 @@ impl SmallDate {
     pub fn from_system_time(t: SystemTime) -> Result<Self> {
         let duration = t.duration_since(SystemTime::UNIX_EPOCH)?;
         let secs = duration.as_secs();
-        let datetime = NaiveDateTime::from_timestamp(secs as i64, 0);
+        let datetime = naive_from_timestamp(secs as i64).ok_or(DateError::Seconds(secs as i64))?;
         naive_to_date(datetime)
     }
 
+    pub fn from_naive(naive: NaiveDateTime) -> Result<Self> {
+        naive_to_date(naive)
+    }
+
+    pub fn from_days(days: u16) -> Self {
+        SmallDate(days)
+    }
+
+    pub fn days(&self) -> u16 {
+        self.0
+    }
+
     pub fn now() -> Result<Self> {
         SmallDate::from_system_time(SystemTime::now())
     }
@@ -64,13 +83,13 @@ impl SmallDate {
     pub fn from_ymd(year: u32, month: u32, day: u32) -> Result<Self> {
         NaiveDate::from_ymd_opt(year as i32, month, day)
             .ok_or_else(|| DateError::Ymd(year, month, day).into())
-            .map(|naive| naive.and_hms(12, 0, 0))
+            .map(|naive| naive.and_hms_opt(12, 0, 0).expect("invalid time"))
             .and_then(naive_to_date)
     }
 
     fn as_ymd(&self) -> (i32, u32, u32) {
         let days = self.0 as i32 + DAYS_FROM_CE_TO_START;
-        let date = NaiveDate::from_num_days_from_ce(days);
+        let date = NaiveDate::from_num_days_from_ce_opt(days).expect("invalid days");
         let year = date.year();
         let month = date.month();
         let day = date.day();
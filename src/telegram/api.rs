@@ -0,0 +1,564 @@
+use crate::fs::{self, IPathBuf};
+use crate::log;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use std::thread;
+use std::time::Duration;
+use thiserror::Error;
+
+pub const DEFAULT_URL: &str = "https://api.telegram.org";
+
+const MAX_ATTEMPTS: u32 = 5;
+
+#[derive(Debug, Error)]
+pub enum ApiError {
+    #[error("Telegram API error {code}: {description}")]
+    Api { code: u16, description: String },
+    #[error("Telegram API unreachable: {0}")]
+    Transport(String),
+    #[error("Telegram API gave up after {0} attempts")]
+    Exhausted(u32),
+}
+
+/// Every Bot API response is wrapped in this
+#[derive(Deserialize)]
+struct Envelope<T> {
+    ok: bool,
+    result: Option<T>,
+    description: Option<String>,
+    error_code: Option<u16>,
+    parameters: Option<Parameters>,
+}
+
+#[derive(Deserialize)]
+struct Parameters {
+    retry_after: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Message {
+    pub message_id: i64,
+    pub document: Option<Document>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct Document {
+    pub file_id: String,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct File {
+    /// Missing when the file is too big for bots to download
+    pub file_path: Option<String>,
+}
+
+enum Outcome<T> {
+    Done(T),
+    Retry(Duration),
+}
+
+/// Bot API calls made in-process, so the token never reaches a process
+/// argument. Rate limited calls are retried after the `retry_after` the API
+/// asks for, server and network failures with exponential backoff
+pub struct Client {
+    agent: ureq::Agent,
+    base_url: String,
+    token: String,
+    backoff: Duration,
+}
+
+impl Client {
+    pub fn new(base_url: &str, token: &str) -> Self {
+        Self {
+            agent: ureq::AgentBuilder::new()
+                .timeout_connect(Duration::from_secs(30))
+                .build(),
+            base_url: base_url.trim_end_matches('/').to_owned(),
+            token: token.to_owned(),
+            backoff: Duration::from_secs(1),
+        }
+    }
+
+    fn url(&self, method: &str) -> String {
+        format!("{}/bot{}/{}", self.base_url, self.token, method)
+    }
+
+    fn json_call<T: DeserializeOwned>(&self, method: &str, body: serde_json::Value) -> Result<T> {
+        let body = body.to_string();
+        self.call(method, |request| {
+            request
+                .set("Content-Type", "application/json")
+                .send_string(&body)
+                .map_err(Box::new)
+        })
+    }
+
+    fn parse<T: DeserializeOwned>(body: &str, status: u16) -> Result<Outcome<T>> {
+        let envelope: Envelope<T> = serde_json::from_str(body)
+            .with_context(|| format!("invalid Telegram API response with status {}", status))?;
+
+        if envelope.ok {
+            return envelope
+                .result
+                .map(Outcome::Done)
+                .context("Telegram API response without result");
+        }
+
+        let code = envelope.error_code.unwrap_or(status);
+        let retry_after = envelope.parameters.and_then(|p| p.retry_after);
+        match (code, retry_after) {
+            (429, Some(secs)) => Ok(Outcome::Retry(Duration::from_secs(secs))),
+            _ => Err(ApiError::Api {
+                code,
+                description: envelope.description.unwrap_or_default(),
+            }
+            .into()),
+        }
+    }
+
+    /// `send` is called again for every attempt
+    fn call<T, F>(&self, method: &str, send: F) -> Result<T>
+    where
+        T: DeserializeOwned,
+        F: Fn(ureq::Request) -> Result<ureq::Response, Box<ureq::Error>>,
+    {
+        let mut backoff = self.backoff;
+
+        for attempt in 1..=MAX_ATTEMPTS {
+            let request = self.agent.post(&self.url(method));
+
+            // None means backing off
+            let retry_after = match send(request).map_err(|e| *e) {
+                Ok(response) => {
+                    let status = response.status();
+                    match Self::parse(&response.into_string()?, status)? {
+                        Outcome::Done(result) => return Ok(result),
+                        Outcome::Retry(wait) => Some(wait),
+                    }
+                }
+                Err(ureq::Error::Status(status, response)) => {
+                    let body = response.into_string().unwrap_or_default();
+                    match Self::parse::<T>(&body, status) {
+                        Ok(Outcome::Done(result)) => return Ok(result),
+                        Ok(Outcome::Retry(wait)) => Some(wait),
+                        Err(_) if status >= 500 => None,
+                        Err(e) => return Err(e),
+                    }
+                }
+                // the error message would include the url, and so the token
+                Err(ureq::Error::Transport(t)) => {
+                    if attempt == MAX_ATTEMPTS {
+                        return Err(ApiError::Transport(t.kind().to_string()).into());
+                    }
+                    None
+                }
+            };
+
+            let wait = retry_after.unwrap_or(backoff);
+            log::warn(&format!(
+                "Telegram {} attempt {} failed, retrying in {}s",
+                method,
+                attempt,
+                wait.as_secs_f32()
+            ));
+            thread::sleep(wait);
+            if retry_after.is_none() {
+                backoff *= 2;
+            }
+        }
+
+        Err(ApiError::Exhausted(MAX_ATTEMPTS).into())
+    }
+
+    pub fn send_message(&self, chat_id: &str, text: &str) -> Result<Message> {
+        self.json_call(
+            "sendMessage",
+            serde_json::json!({ "chat_id": chat_id, "text": text }),
+        )
+    }
+
+    pub fn forward_message(&self, chat_id: &str, from_chat_id: &str, message_id: i64) -> Result<Message> {
+        self.json_call(
+            "forwardMessage",
+            serde_json::json!({ "chat_id": chat_id, "from_chat_id": from_chat_id, "message_id": message_id }),
+        )
+    }
+
+    /// Like `forward_message`, without the link to the original message
+    pub fn copy_message(&self, chat_id: &str, from_chat_id: &str, message_id: i64) -> Result<Message> {
+        self.json_call(
+            "copyMessage",
+            serde_json::json!({ "chat_id": chat_id, "from_chat_id": from_chat_id, "message_id": message_id }),
+        )
+    }
+
+    pub fn get_file(&self, file_id: &str) -> Result<File> {
+        self.json_call("getFile", serde_json::json!({ "file_id": file_id }))
+    }
+
+    /// Downloads a `file_path` returned by `get_file`
+    pub fn download(&self, file_path: &str, to: &Path) -> Result<()> {
+        let url = format!("{}/file/bot{}/{}", self.base_url, self.token, file_path);
+        let response = match self.agent.get(&url).call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(code, response)) => {
+                return Err(ApiError::Api {
+                    code,
+                    description: response.status_text().to_owned(),
+                }
+                .into())
+            }
+            Err(ureq::Error::Transport(t)) => return Err(ApiError::Transport(t.kind().to_string()).into()),
+        };
+
+        fs::create_parent_all(to)?;
+        let mut file = std::fs::File::create(to)?;
+        std::io::copy(&mut response.into_reader(), &mut file)
+            .with_context(|| format!("Failed to download {}", to.to_string()))?;
+        Ok(())
+    }
+
+    /// The file is streamed as multipart/form-data
+    pub fn send_document(&self, chat_id: &str, path: &Path, caption: &str) -> Result<Message> {
+        let mut form = Multipart::new();
+        form.text("chat_id", chat_id);
+        form.text("caption", caption);
+        // otherwise some files come back as a photo or video, without a file id to fetch
+        form.text("disable_content_type_detection", "true");
+        form.file("document", path)?;
+
+        self.call("sendDocument", |request| form.send(request))
+            .with_context(|| format!("Failed to send {}", path.to_string()))
+    }
+
+    /// Sends a photo or video that shows up with a preview
+    pub fn send_media(&self, chat_id: &str, kind: Media, path: &Path, caption: &str) -> Result<Message> {
+        let mut form = Multipart::new();
+        form.text("chat_id", chat_id);
+        form.text("caption", caption);
+        form.file(kind.name(), path)?;
+
+        let method = match kind {
+            Media::Photo => "sendPhoto",
+            Media::Video => "sendVideo",
+        };
+        self.call(method, |request| form.send(request))
+            .with_context(|| format!("Failed to send {}", path.to_string()))
+    }
+
+    /// Sends 2 to 10 photos and videos as an album, captioned by its first item
+    pub fn send_media_group(
+        &self,
+        chat_id: &str,
+        items: &[(Media, &Path)],
+        caption: &str,
+    ) -> Result<Vec<Message>> {
+        let media = items
+            .iter()
+            .enumerate()
+            .map(|(i, (kind, _))| {
+                let mut item =
+                    serde_json::json!({ "type": kind.name(), "media": format!("attach://file{}", i) });
+                if i == 0 {
+                    item["caption"] = caption.into();
+                }
+                item
+            })
+            .collect::<Vec<_>>();
+
+        let mut form = Multipart::new();
+        form.text("chat_id", chat_id);
+        form.text("media", &serde_json::Value::from(media).to_string());
+        for (i, (_, path)) in items.iter().enumerate() {
+            form.file(&format!("file{}", i), path)?;
+        }
+
+        self.call("sendMediaGroup", |request| form.send(request))
+            .with_context(|| format!("Failed to send album {}", caption))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Media {
+    Photo,
+    Video,
+}
+
+impl Media {
+    fn name(self) -> &'static str {
+        match self {
+            Media::Photo => "photo",
+            Media::Video => "video",
+        }
+    }
+}
+
+enum Part<'a> {
+    Text(String),
+    File(&'a Path),
+}
+
+/// A multipart/form-data body with files streamed from disk, rebuilt for every attempt
+struct Multipart<'a> {
+    boundary: String,
+    parts: Vec<Part<'a>>,
+    len: u64,
+}
+
+impl<'a> Multipart<'a> {
+    fn new() -> Self {
+        let boundary = format!(
+            "storm{:x}",
+            md5::compute(format!("{:?}", std::time::SystemTime::now()))
+        );
+        Self {
+            boundary,
+            parts: vec![],
+            len: 0,
+        }
+    }
+
+    fn push_text(&mut self, txt: String) {
+        self.len += txt.len() as u64;
+        self.parts.push(Part::Text(txt));
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        self.push_text(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            self.boundary, name, value
+        ));
+    }
+
+    fn file(&mut self, name: &str, path: &'a Path) -> Result<()> {
+        let filename = path
+            .file_name()
+            .context("no filename")?
+            .to_string_lossy()
+            .replace(['"', '\r', '\n'], "_");
+        self.push_text(format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            self.boundary, name, filename
+        ));
+        self.len += std::fs::metadata(path)?.len();
+        self.parts.push(Part::File(path));
+        self.push_text("\r\n".into());
+        Ok(())
+    }
+
+    fn send(&self, request: ureq::Request) -> Result<ureq::Response, Box<ureq::Error>> {
+        let tail = format!("--{}--\r\n", self.boundary);
+
+        let mut body: Box<dyn Read + '_> = Box::new(std::io::empty());
+        for part in &self.parts {
+            body = match part {
+                Part::Text(txt) => Box::new(body.chain(Cursor::new(txt.as_bytes()))),
+                Part::File(path) => {
+                    let file = fs::open(path).map_err(|e| {
+                        Box::new(ureq::Error::from(std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            e.to_string(),
+                        )))
+                    })?;
+                    Box::new(body.chain(file))
+                }
+            };
+        }
+
+        request
+            .set(
+                "Content-Type",
+                &format!("multipart/form-data; boundary={}", self.boundary),
+            )
+            .set("Content-Length", &(self.len + tail.len() as u64).to_string())
+            .send(body.chain(Cursor::new(tail.as_bytes())))
+            .map_err(Box::new)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// Answers each request with the next canned response, reporting the
+    /// request lines it saw
+    fn fake_server(responses: Vec<(u16, &'static str)>) -> (String, mpsc::Receiver<String>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (tx, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+
+                let mut content_length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" {
+                        break;
+                    }
+                    if let Some(v) = line.to_lowercase().strip_prefix("content-length:") {
+                        content_length = v.trim().parse().unwrap();
+                    }
+                }
+                let mut body_in = vec![0; content_length];
+                reader.read_exact(&mut body_in).unwrap();
+                // tests that don't check requests drop the receiver
+                let _ = tx.send(format!(
+                    "{}{}",
+                    request_line.trim(),
+                    String::from_utf8_lossy(&body_in)
+                ));
+
+                let response = format!(
+                    "HTTP/1.1 {} X\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (url, rx)
+    }
+
+    fn client(url: &str) -> Client {
+        let mut client = Client::new(url, "123:abc");
+        client.backoff = Duration::from_millis(1);
+        client
+    }
+
+    #[test]
+    fn test_retries() -> Result<()> {
+        let (url, requests) = fake_server(vec![
+            (
+                429,
+                r#"{"ok":false,"error_code":429,"description":"Too Many Requests","parameters":{"retry_after":0}}"#,
+            ),
+            (502, "Bad Gateway"),
+            (200, r#"{"ok":true,"result":{"message_id":42}}"#),
+        ]);
+
+        let message = client(&url).send_message("-100", "say \"hi\"")?;
+        assert_eq!(message.message_id, 42);
+
+        let first = requests.recv()?;
+        assert!(first.starts_with("POST /bot123:abc/sendMessage"));
+        assert!(first.ends_with(r#"{"chat_id":"-100","text":"say \"hi\""}"#));
+        assert_eq!(requests.iter().count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_errors() -> Result<()> {
+        let (url, _) = fake_server(vec![(
+            400,
+            r#"{"ok":false,"error_code":400,"description":"Bad Request: chat not found"}"#,
+        )]);
+
+        let err = client(&url).send_message("-100", "hi").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Telegram API error 400: Bad Request: chat not found"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_document() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("alien.txt");
+        std::fs::write(&path, "alien")?;
+
+        let (url, requests) = fake_server(vec![(
+            200,
+            r#"{"ok":true,"result":{"message_id":7,"document":{"file_id":"BQACAgQ"}}}"#,
+        )]);
+
+        let message = client(&url).send_document("-100", &path, "books/alien.txt")?;
+        assert_eq!(message.message_id, 7);
+        assert_eq!(message.document.unwrap().file_id, "BQACAgQ");
+
+        let request = requests.recv()?;
+        assert!(request.starts_with("POST /bot123:abc/sendDocument"));
+        assert!(request.contains("name=\"caption\"\r\n\r\nbooks/alien.txt\r\n"));
+        assert!(request
+            .contains("filename=\"alien.txt\"\r\nContent-Type: application/octet-stream\r\n\r\nalien\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_media_group() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let beach = dir.path().join("beach.jpg");
+        let party = dir.path().join("party.mp4");
+        std::fs::write(&beach, "beach")?;
+        std::fs::write(&party, "party")?;
+
+        let (url, requests) = fake_server(vec![(
+            200,
+            r#"{"ok":true,"result":[{"message_id":8},{"message_id":9}]}"#,
+        )]);
+
+        let messages = client(&url).send_media_group(
+            "-100",
+            &[(Media::Photo, &beach), (Media::Video, &party)],
+            "dcim/camera",
+        )?;
+        assert_eq!(
+            messages.iter().map(|m| m.message_id).collect::<Vec<_>>(),
+            vec![8, 9]
+        );
+
+        let request = requests.recv()?;
+        assert!(request.starts_with("POST /bot123:abc/sendMediaGroup"));
+        assert!(request.contains(
+            r#"[{"caption":"dcim/camera","media":"attach://file0","type":"photo"},{"media":"attach://file1","type":"video"}]"#
+        ));
+        assert!(request.contains("name=\"file0\"; filename=\"beach.jpg\""));
+        assert!(request.contains("\r\n\r\nparty\r\n"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_file() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let to = dir.path().join("books/alien.txt");
+
+        let (url, requests) = fake_server(vec![
+            (
+                200,
+                r#"{"ok":true,"result":{"file_path":"documents/file_3.txt"}}"#,
+            ),
+            (200, "alien"),
+        ]);
+
+        let client = client(&url);
+        let file = client.get_file("BQACAgQ")?;
+        assert_eq!(file.file_path.as_deref(), Some("documents/file_3.txt"));
+        client.download(&file.file_path.unwrap(), &to)?;
+        assert_eq!(std::fs::read_to_string(&to)?, "alien");
+
+        assert!(requests.recv()?.ends_with(r#"{"file_id":"BQACAgQ"}"#));
+        assert!(requests
+            .recv()?
+            .starts_with("GET /file/bot123:abc/documents/file_3.txt"));
+
+        Ok(())
+    }
+}
@@ -0,0 +1,111 @@
+use crate::db::{self, file::File};
+use crate::fs::{self, IPathBuf};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+
+/// Catalog entries of files already sent, appended as each upload finishes so
+/// that an interrupted run keeps them. Lines are catalog lines with the
+/// filename replaced by the path relative to the buffer
+pub(super) struct Journal {
+    path: PathBuf,
+}
+
+impl Journal {
+    /// Kept next to the catalog
+    pub fn new(db_path: &Path) -> Self {
+        let mut name = db_path.file_name().unwrap_or_default().to_owned();
+        name.push(".journal");
+        Self {
+            path: db_path.with_file_name(name),
+        }
+    }
+
+    pub fn record(&self, path: &Path, file: &File) -> Result<()> {
+        let entry = File {
+            filename: path.to_string(),
+            ..file.clone()
+        };
+        fs::append(&self.path, &format!("{}\n", entry))
+    }
+
+    pub fn entries(&self) -> Result<Vec<(PathBuf, File)>> {
+        if !self.path.exists() {
+            return Ok(vec![]);
+        }
+
+        let mut entries = vec![];
+        for line in fs::read_lines(&self.path)? {
+            if line.trim().is_empty() {
+                continue;
+            }
+            let mut file = File::from_line(&line)?;
+            let path = PathBuf::from(&file.filename);
+            file.filename = path
+                .file_name()
+                .with_context(|| format!("invalid journal entry {}", line))?
+                .to_string_lossy()
+                .into();
+            entries.push((path, file));
+        }
+        Ok(entries)
+    }
+
+    /// Adds the recorded entries to the catalog and clears the journal
+    pub fn compact(&self, db_path: &Path) -> Result<usize> {
+        let entries = self.entries()?;
+        if entries.is_empty() {
+            return Ok(0);
+        }
+
+        let n = entries.len();
+        let mut db = db::read(db_path)?;
+        for (path, file) in entries {
+            db.insert(&path, file)?;
+        }
+        db::write(db, db_path)?;
+        fs::remove_file(&self.path)?;
+
+        Ok(n)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_journal() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let db_path = dir.path().join("telegram.txt");
+        std::fs::write(&db_path, "=\nbooks\n=\n0\nspaceship.txt;44;201030\n")?;
+
+        let journal = Journal::new(&db_path);
+        assert!(journal.entries()?.is_empty());
+
+        let alien = File::from_line("alien.txt;1;201030;d41d8cd98f00b204e9800998ecf8427e;-100:7:BQACAgQ")?;
+        journal.record(Path::new("books/fiction/alien.txt"), &alien)?;
+        journal.record(Path::new("beach.jpg"), &File::from_line("beach.jpg;2;211229")?)?;
+
+        let entries = journal.entries()?;
+        assert_eq!(entries[0].0, PathBuf::from("books/fiction/alien.txt"));
+        assert_eq!(entries[0].1.to_string(), alien.to_string());
+        assert_eq!(entries[1].0, PathBuf::from("beach.jpg"));
+
+        assert_eq!(journal.compact(&db_path)?, 2);
+        assert!(journal.entries()?.is_empty());
+        assert_eq!(
+            std::fs::read_to_string(&db_path)?.trim(),
+            "=
+books
+  fiction
+=
+0
+beach.jpg;2;211229
+spaceship.txt;44;201030
+2
+alien.txt;1;201030;d41d8cd98f00b204e9800998ecf8427e;-100:7:BQACAgQ"
+        );
+
+        Ok(())
+    }
+}
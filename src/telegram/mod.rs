@@ -1,48 +1,262 @@
+mod api;
+mod journal;
+mod rate;
 mod skip;
 
+use crate::db::file::File;
+use crate::format::{self, Format};
 use crate::fs::{self, IPathBuf};
+use crate::plan::{self, Action};
 use crate::provider::ProviderId;
-use crate::shell;
 use crate::{config, log};
-use crate::{db, provider};
-use anyhow::Result;
+use crate::{db, provider, zip};
+use anyhow::{Context, Result};
+use api::{Client, Media};
+use journal::Journal;
+use once_cell::sync::OnceCell;
+use rate::RateLimit;
 use skip::{should_process, SkipReason};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
 use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::mpsc::channel;
 use walkdir::WalkDir;
+use workerpool::thunk::{Thunk, ThunkWorker};
+use workerpool::Pool;
 
 pub const PROVIDER_ID: &ProviderId = "telegram";
 
-pub fn upload(path: &Path) -> Result<shell::ShellCmd> {
-    let filepath = path.to_string();
-    let token = &config::get().yaml.telegram.token;
+const ALBUM_SIZE: usize = 10;
+const DEFAULT_MESSAGES_PER_MINUTE: u32 = 20;
+
+// Bot API limits for sendPhoto and sendVideo
+const MAX_PHOTO_BYTES: u64 = 10 * 1024 * 1024;
+const MAX_VIDEO_BYTES: u64 = 50 * 1024 * 1024;
+// Bot API limit for sendDocument, for when max_object_kb isn't set
+const MAX_DOCUMENT_KB: u32 = 50 * 1024;
+
+static RATE_LIMIT: OnceCell<RateLimit> = OnceCell::new();
+
+struct WorkerResult(usize, Result<(PathBuf, (PathBuf, File))>);
+
+fn wait_turn(chat_id: &str) {
+    RATE_LIMIT
+        .get_or_init(|| {
+            let messages = config::get().yaml.telegram.messages_per_minute;
+            RateLimit::per_minute(messages.unwrap_or(DEFAULT_MESSAGES_PER_MINUTE))
+        })
+        .wait(chat_id)
+}
+
+fn client() -> Result<Client> {
+    let token = config::get().telegram_token()?;
+    let url = config::get()
+        .yaml
+        .telegram
+        .api_url
+        .as_deref()
+        .unwrap_or(api::DEFAULT_URL);
+    Ok(Client::new(url, token))
+}
+
+/// Where an uploaded file lives, kept in the catalog as `chat_id:message_id:file_id`
+#[derive(Debug, PartialEq)]
+pub struct RemoteId {
+    pub chat_id: String,
+    pub message_id: i64,
+    pub file_id: String,
+}
+
+impl fmt::Display for RemoteId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.chat_id, self.message_id, self.file_id)
+    }
+}
+
+impl FromStr for RemoteId {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut parts = s.splitn(3, ':');
+        let mut next = || parts.next().filter(|p| !p.is_empty());
+        let err = || anyhow!("invalid Telegram id {}", s);
+        Ok(Self {
+            chat_id: next().ok_or_else(err)?.to_owned(),
+            message_id: next().ok_or_else(err)?.parse().with_context(err)?,
+            file_id: next().ok_or_else(err)?.to_owned(),
+        })
+    }
+}
+
+/// Big files are sent in parts, whose ids are kept comma separated in the catalog
+fn join_ids(ids: &[RemoteId]) -> Option<String> {
+    if ids.is_empty() {
+        return None;
+    }
+    Some(ids.iter().map(|id| id.to_string()).collect::<Vec<_>>().join(","))
+}
+
+fn parse_ids(s: &str) -> Result<Vec<RemoteId>> {
+    s.split(',').map(RemoteId::from_str).collect()
+}
+
+pub fn upload(path: &Path) -> Result<()> {
+    send(path, &caption(path)?).map(|_| ())
+}
+
+/// None in dry-run mode
+fn send(path: &Path, caption: &str) -> Result<Option<RemoteId>> {
+    if plan::skip(Action::Upload {
+        path: path.into(),
+        provider: PROVIDER_ID.into(),
+    }) {
+        return Ok(None);
+    }
+
     let chat_id = &config::get().yaml.telegram.chat_id;
 
+    wait_turn(chat_id);
+    let message = client()?.send_document(chat_id, path, caption)?;
+    log::debug(&format!("{} sent as message {}", caption, message.message_id));
+
+    let document = message.document.context("no document in Telegram response")?;
+    Ok(Some(RemoteId {
+        chat_id: chat_id.to_owned(),
+        message_id: message.message_id,
+        file_id: document.file_id,
+    }))
+}
+
+/// The path relative to the buffer
+fn caption(path: &Path) -> Result<String> {
     let buffer = &provider::get(PROVIDER_ID)?.buffer;
-    let caption = filepath
+    Ok(path
+        .to_string()
         .trim_start_matches(&buffer.to_string())
         .trim_start_matches('/')
-        .replace('"', "\\\"");
+        .to_owned())
+}
 
-    let document = format!("document=@\"{}\"", filepath);
-    let url = format!(
-        "https://api.telegram.org/bot{}/sendDocument?chat_id={}&caption={}",
-        token, chat_id, caption
-    );
+/// How a file can be sent so that it shows a preview, if it can
+fn media(path: &Path) -> Option<Media> {
+    let (kind, max_bytes) = match format::get_format(path) {
+        Format::Image => (Media::Photo, MAX_PHOTO_BYTES),
+        Format::Video => (Media::Video, MAX_VIDEO_BYTES),
+        Format::Unsupported => return None,
+    };
+    if std::fs::metadata(path).ok()?.len() > max_bytes {
+        return None;
+    }
+    Some(kind)
+}
+
+type Album<'a> = Vec<(Media, &'a Path)>;
+
+/// Previewable files grouped by folder, split into albums
+fn albums(filepaths: &[PathBuf]) -> Vec<(&Path, Vec<Album<'_>>)> {
+    let mut folders: BTreeMap<&Path, Album> = BTreeMap::new();
+    for path in filepaths {
+        if let (Some(kind), Some(folder)) = (media(path), path.parent()) {
+            folders.entry(folder).or_default().push((kind, path));
+        }
+    }
 
-    let args = &["-F", &document, &url];
-    shell::out("curl", args).map_err(|e| e.into())
+    folders
+        .into_iter()
+        .map(|(folder, items)| (folder, items.chunks(ALBUM_SIZE).map(|c| c.to_vec()).collect()))
+        .collect()
 }
 
-pub fn send_message(txt: &str) -> Result<shell::ShellCmd> {
+/// Posts photos and videos as albums captioned by their folder, returning how many failed
+fn send_albums(filepaths: &[PathBuf]) -> Result<usize> {
     let chat_id = &config::get().yaml.telegram.chat_id;
-    let token = &config::get().yaml.telegram.token;
+    let client = client()?;
 
-    let url = format!("https://api.telegram.org/bot{}/sendMessage", token);
-    let escaped_txt = txt.replace('"', "\\\"");
-    let data = format!(r#"{{"chat_id": "{}", "text": "{}"}}"#, chat_id, escaped_txt);
+    let mut errors = 0;
+    for (folder, albums) in albums(filepaths) {
+        let n = albums.len();
+        for (i, album) in albums.into_iter().enumerate() {
+            if plan::skip(Action::Album {
+                folder: folder.into(),
+                files: album.len(),
+            }) {
+                continue;
+            }
+
+            let mut caption = caption(folder)?;
+            if n > 1 {
+                caption = format!("{} ({}/{})", caption, i + 1, n);
+            }
+
+            // albums need at least 2 items
+            wait_turn(chat_id);
+            let res = match album.as_slice() {
+                [(kind, path)] => client.send_media(chat_id, *kind, path, &caption).map(|_| ()),
+                _ => client.send_media_group(chat_id, &album, &caption).map(|_| ()),
+            };
+            match res {
+                Ok(()) => log::info(&format!("Album {} sent", caption)),
+                Err(e) => {
+                    log::error(&format!("{:#}", e));
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    Ok(errors)
+}
+
+pub fn send_message(txt: &str) -> Result<()> {
+    let chat_id = &config::get().yaml.telegram.chat_id;
 
-    let args = &["-d", &data, "-H", "Content-Type: application/json", &url];
-    shell::out("curl", args).map_err(|e| e.into())
+    wait_turn(chat_id);
+    let message = client()?.send_message(chat_id, txt)?;
+    log::debug(&format!("Sent message {}", message.message_id));
+
+    Ok(())
+}
+
+/// Sends a file, in parts of at most `max_kb` if it is bigger, returning the
+/// ids of what was sent in order. The file itself is left untouched
+fn send_file(path: &Path, max_kb: u32) -> Result<Vec<RemoteId>> {
+    let caption = caption(path)?;
+    if plan::is_dry_run() || std::fs::metadata(path)?.len() <= max_kb as u64 * 1024 {
+        return Ok(send(path, &caption)?.into_iter().collect());
+    }
+
+    log::info(&format!("Splitting {}", path.to_string()));
+    let tmp = tempfile::tempdir()?;
+    let filename = path.file_name().context("no filename")?;
+    let parts = zip::split_to(path, max_kb, &tmp.path().join(filename))?;
+
+    let mut ids = vec![];
+    for (i, part) in parts.iter().enumerate() {
+        let caption = zip::volume_path(Path::new(&caption), i + 1).to_string();
+        ids.extend(send(part, &caption)?);
+    }
+    Ok(ids)
+}
+
+/// Downloads a file to `to` with `get`, joining its parts if it was split
+fn fetch(ids: &[RemoteId], to: &Path, get: impl Fn(&RemoteId, &Path) -> Result<()>) -> Result<()> {
+    if let [id] = ids {
+        return get(id, to);
+    }
+
+    let mut parts = vec![];
+    for (i, id) in ids.iter().enumerate() {
+        let part = zip::volume_path(to, i + 1);
+        get(id, &part)?;
+        parts.push(part);
+    }
+
+    let result = zip::join(&parts, to);
+    for part in &parts {
+        fs::remove_file(part)?;
+    }
+    result
 }
 
 fn get_filepaths(path: &Path) -> Result<Vec<PathBuf>> {
@@ -50,6 +264,10 @@ fn get_filepaths(path: &Path) -> Result<Vec<PathBuf>> {
 
     for entry in WalkDir::new(path).into_iter().filter_map(|e| e.ok()) {
         let reason = should_process(&entry);
+        if !matches!(reason, SkipReason::Directory) {
+            plan::decision(entry.path(), &reason);
+        }
+
         if let SkipReason::NoSkip = reason {
             filepaths.push(entry.path().into());
         }
@@ -59,33 +277,147 @@ fn get_filepaths(path: &Path) -> Result<Vec<PathBuf>> {
 }
 
 pub fn upload_buffer() -> Result<()> {
+    upload_folder(&provider::get(PROVIDER_ID)?.buffer)
+}
+
+/// Uploads every file in the folder, recording and removing each one that
+/// succeeds. Entries go to a journal first, so that the catalog is only
+/// rewritten once and an interrupted run doesn't send files again
+pub fn upload_folder(folder: &Path) -> Result<()> {
     let buffer = &provider::get(PROVIDER_ID)?.buffer;
-    fs::remove_os_files(buffer)?;
+    fs::remove_os_files(folder)?;
+
+    let db_path = &config::get().yaml.telegram.db_path;
+    let journal = Journal::new(db_path);
+
+    // left behind by an interrupted run
+    let sent = journal
+        .entries()?
+        .into_iter()
+        .map(|(path, file)| (path, file.hash))
+        .collect::<HashMap<_, _>>();
+
+    let mut filepaths = vec![];
+    for filepath in get_filepaths(folder)? {
+        let already_sent = match sent.get(filepath.strip_prefix(buffer)?) {
+            Some(hash) => hash.as_deref() == Some(fs::md5(&filepath)?.as_str()),
+            None => false,
+        };
+        if already_sent {
+            log::info(&format!("{} was already sent", filepath.to_string()));
+            fs::remove_file(&filepath)?;
+        } else {
+            filepaths.push(filepath);
+        }
+    }
+
+    // before the originals are split, sent and removed
+    let mut errors = if config::get().yaml.telegram.media_groups {
+        send_albums(&filepaths)?
+    } else {
+        0
+    };
+
+    let max_kb = provider::get(PROVIDER_ID)?
+        .max_object_kb
+        .unwrap_or(MAX_DOCUMENT_KB);
 
-    let filepaths = get_filepaths(buffer)?;
     let n = filepaths.len();
     log::setup("tu".into(), n)?;
 
-    let db_path = &config::get().yaml.telegram.db_path;
+    let n_workers = config::get().yaml.parallelism.workers;
+    let pool: Pool<ThunkWorker<WorkerResult>> = Pool::new(n_workers as usize);
 
-    dbg!(&filepaths);
+    let (tx, rx) = channel();
 
-    let mut errors = 0;
     for (i, filepath) in filepaths.into_iter().enumerate() {
-        match upload(&filepath) {
-            Ok(cmd) => {
-                dbg!(cmd);
+        pool.execute_to(
+            tx.clone(),
+            Thunk::of(move || {
+                log::start(i);
+                let result = send_file(&filepath, max_kb).and_then(|ids| {
+                    let entry = db::describe(&filepath, buffer, join_ids(&ids))?;
+                    Ok((filepath, entry))
+                });
+                WorkerResult(i, result)
+            }),
+        );
+    }
 
-                let mut db = db::read(db_path)?;
-                db.add_file(&filepath, buffer)?;
-                db::write(db, db_path)?;
+    for WorkerResult(i, result) in rx.iter().take(n) {
+        let result = result.and_then(|(filepath, (path, file))| {
+            journal.record(&path, &file)?;
+            fs::remove_file(&filepath)
+        });
+        match result {
+            Ok(()) => log::success(i),
+            Err(e) => {
+                log::failure(i, e);
+                errors += 1;
+            }
+        }
+    }
 
-                fs::remove_file(&filepath)?;
+    journal.compact(db_path)?;
 
-                log::success(i);
-            }
+    if errors > 0 {
+        Err(anyhow!("{} files failed", errors))
+    } else {
+        Ok(())
+    }
+}
+
+/// Uploaded files matching the glob, with the ids of their parts. Files
+/// uploaded before ids were recorded can't be fetched and are only reported
+fn uploaded(pattern: &str) -> Result<Vec<(PathBuf, Vec<RemoteId>)>> {
+    let db = db::read(&config::get().yaml.telegram.db_path)?;
+    let query = db::query::Query::new(Some(pattern), None, None, None, None, None)?;
+
+    let mut files = vec![];
+    for (path, file) in db.entries() {
+        if !query.matches(&path, file) {
+            continue;
+        }
+        match &file.remote_id {
+            Some(ids) => files.push((path, parse_ids(ids)?)),
+            None => log::warn(&format!("{} has no Telegram id", path.to_string())),
+        }
+    }
+
+    if files.is_empty() {
+        return Err(anyhow!("no uploaded file matches {}", pattern));
+    }
+    Ok(files)
+}
+
+/// Downloads uploaded files into a folder, keeping their catalog paths
+pub fn download(pattern: &str, to: &Path) -> Result<()> {
+    let files = uploaded(pattern)?;
+    if plan::skip(Action::Pull {
+        remote: PROVIDER_ID.into(),
+        files: files.iter().map(|(path, _)| path.clone()).collect(),
+        to: to.into(),
+    }) {
+        return Ok(());
+    }
+
+    let client = client()?;
+    let n = files.len();
+    log::setup("tf".into(), n)?;
+
+    let mut errors = 0;
+    for (i, (path, ids)) in files.into_iter().enumerate() {
+        let res = fetch(&ids, &to.join(&path), |id, to| {
+            let file = client.get_file(&id.file_id)?;
+            let file_path = file
+                .file_path
+                .context("Telegram doesn't let bots download files this big")?;
+            client.download(&file_path, to)
+        });
+        match res {
+            Ok(()) => log::success(i),
             Err(e) => {
-                log::failure(i, e);
+                log::failure(i, e.context(path.to_string()));
                 errors += 1;
             }
         }
@@ -97,3 +429,137 @@ pub fn upload_buffer() -> Result<()> {
         Ok(())
     }
 }
+
+/// Sends uploaded files to another chat. Copies don't link back to the original message
+pub fn forward(pattern: &str, chat_id: &str, copy: bool) -> Result<()> {
+    let files = uploaded(pattern)?;
+    let client = client()?;
+
+    let mut errors = 0;
+    for (path, ids) in files {
+        if plan::skip(Action::Forward {
+            path: path.clone(),
+            chat_id: chat_id.into(),
+        }) {
+            continue;
+        }
+
+        for id in ids {
+            wait_turn(chat_id);
+            let res = if copy {
+                client.copy_message(chat_id, &id.chat_id, id.message_id)
+            } else {
+                client.forward_message(chat_id, &id.chat_id, id.message_id)
+            };
+            match res {
+                Ok(message) => log::info(&format!(
+                    "{} sent as message {}",
+                    path.to_string(),
+                    message.message_id
+                )),
+                Err(e) => {
+                    log::error(&format!("{}: {:#}", path.to_string(), e));
+                    errors += 1;
+                }
+            }
+        }
+    }
+
+    if errors > 0 {
+        Err(anyhow!("{} files failed", errors))
+    } else {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_albums() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let mut filepaths = vec![];
+        for name in (0..12).map(|i| format!("camera/{:02}.jpg", i)).chain([
+            "camera/notes.txt".into(),
+            "camera/party.mp4".into(),
+            "shots/sea.png".into(),
+        ]) {
+            let path = dir.path().join(name);
+            fs::create_parent_all(&path)?;
+            std::fs::write(&path, "x")?;
+            filepaths.push(path);
+        }
+
+        let albums = albums(&filepaths);
+        let sizes = albums
+            .iter()
+            .map(|(folder, albums)| {
+                (
+                    folder.file_name().unwrap().to_string_lossy().to_string(),
+                    albums.iter().map(|a| a.len()).collect::<Vec<_>>(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            sizes,
+            vec![("camera".into(), vec![10, 3]), ("shots".into(), vec![1])]
+        );
+        assert_eq!(albums[0].1[1][2], (Media::Video, filepaths[13].as_path()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parts_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let big = dir.path().join("party.mp4");
+        let contents = (0..2500).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(&big, &contents)?;
+
+        let sent = dir.path().join("sent");
+        std::fs::create_dir(&sent)?;
+        let parts = zip::split_to(&big, 1, &sent.join("party.mp4"))?;
+        assert_eq!(parts.len(), 3);
+        assert!(big.exists());
+
+        // message ids stand for the parts they were sent as
+        let ids = (1..=3)
+            .map(|i| RemoteId {
+                chat_id: "-1001".into(),
+                message_id: i,
+                file_id: format!("BQACAgQ{}", i),
+            })
+            .collect::<Vec<_>>();
+        let stored = join_ids(&ids).unwrap();
+        assert_eq!(parse_ids(&stored)?, ids);
+
+        let restored = dir.path().join("restored/party.mp4");
+        fs::create_parent_all(&restored)?;
+        fetch(&parse_ids(&stored)?, &restored, |id, to| {
+            std::fs::copy(&parts[id.message_id as usize - 1], to)?;
+            Ok(())
+        })?;
+        assert_eq!(std::fs::read(&restored)?, contents);
+        assert!(zip::volumes(&restored).is_empty());
+
+        assert_eq!(join_ids(&[]), None);
+        assert_eq!(parse_ids("-1001:42:BQACAgQ")?.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remote_id() -> Result<()> {
+        let id = RemoteId::from_str("-1001:42:BQACAgQ:x")?;
+        assert_eq!(id.chat_id, "-1001");
+        assert_eq!(id.message_id, 42);
+        assert_eq!(id.file_id, "BQACAgQ:x");
+        assert_eq!(id.to_string(), "-1001:42:BQACAgQ:x");
+
+        assert!(RemoteId::from_str("-1001:42").is_err());
+        assert!(RemoteId::from_str("-1001:x:BQACAgQ").is_err());
+
+        Ok(())
+    }
+}
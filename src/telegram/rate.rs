@@ -0,0 +1,61 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Spaces out messages to each chat, shared by all upload workers, so that
+/// Telegram doesn't start answering with 429s
+pub(super) struct RateLimit {
+    interval: Duration,
+    next: Mutex<HashMap<String, Instant>>,
+}
+
+impl RateLimit {
+    pub fn per_minute(messages: u32) -> Self {
+        Self {
+            interval: Duration::from_secs(60) / messages.max(1),
+            next: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Blocks until the chat can take another message
+    pub fn wait(&self, chat_id: &str) {
+        let now = Instant::now();
+        let slot = {
+            let mut next = self.next.lock().expect("failed to lock rate limit");
+            let slot = next.get(chat_id).copied().unwrap_or(now).max(now);
+            next.insert(chat_id.to_owned(), slot + self.interval);
+            slot
+        };
+        thread::sleep(slot - now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_rate_limit() {
+        // one every 50ms
+        let limit = Arc::new(RateLimit::per_minute(1200));
+        let start = Instant::now();
+
+        let workers = (0..3)
+            .map(|_| {
+                let limit = limit.clone();
+                thread::spawn(move || limit.wait("-100"))
+            })
+            .collect::<Vec<_>>();
+        for worker in workers {
+            worker.join().unwrap();
+        }
+        assert!(start.elapsed() >= Duration::from_millis(100));
+
+        // other chats have their own slots
+        let start = Instant::now();
+        limit.wait("-200");
+        assert!(start.elapsed() < Duration::from_millis(50));
+    }
+}
@@ -0,0 +1,181 @@
+use super::{Backend, Entry};
+use crate::fs::IPathBuf;
+use crate::shell::{self, ShellCmd, ShellError};
+use anyhow::{Context, Result};
+use chrono::NaiveDateTime;
+use std::path::{Path, PathBuf};
+
+/// The `7z` binary. Passwords are given as arguments, so they are visible to
+/// other users in `ps`
+pub(super) struct Command;
+
+fn pass(password: &str) -> String {
+    format!("-p{}", password)
+}
+
+/// Errors show the command, which has the password in it
+fn redact(mut error: ShellError) -> ShellError {
+    if let ShellError::NonZero { cmd } | ShellError::Spawn { cmd, .. } = &mut error {
+        for arg in cmd.args.iter_mut().filter(|a| a.starts_with("-p")) {
+            *arg = "-p***".into();
+        }
+    }
+    error
+}
+
+fn run(args: &[&str]) -> Result<ShellCmd> {
+    Ok(shell::out("7z", args).map_err(redact)?)
+}
+
+impl Backend for Command {
+    fn create(&self, password: &str, from: &Path, to: &Path) -> Result<()> {
+        let from_file = from.metadata().map(|m| m.is_file()).unwrap_or(false);
+        let from_str = if from_file {
+            from.to_string()
+        } else {
+            format!("{}/*", from.to_string())
+        };
+
+        run(&["a", &pass(password), &to.to_string(), &from_str])?;
+        Ok(())
+    }
+
+    fn test(&self, password: &str, from: &Path) -> Result<()> {
+        run(&["t", &from.to_string(), &pass(password)])?;
+        Ok(())
+    }
+
+    fn extract(&self, password: &str, from: &Path, to_folder: &Path) -> Result<()> {
+        let to_str = format!("-o{}", to_folder.to_string());
+        run(&["x", &from.to_string(), &pass(password), &to_str, "-aos"])?;
+        Ok(())
+    }
+
+    fn list(&self, password: &str, from: &Path) -> Result<Vec<Entry>> {
+        let out = run(&["l", "-slt", &from.to_string(), &pass(password)])?;
+        parse_slt(&out.res.context("no res")?.stdout)
+    }
+}
+
+/// Entries come after the `----------` line, as blank line separated blocks
+/// of `key = value` lines
+fn parse_slt(txt: &str) -> Result<Vec<Entry>> {
+    let body = txt
+        .split_once("\n----------\n")
+        .map(|(_, b)| b)
+        .context("no entries in 7z listing")?;
+
+    let mut entries = vec![];
+
+    for block in body.split("\n\n") {
+        let mut path = None;
+        let mut bytes = 0;
+        let mut modified = None;
+        let mut is_dir = false;
+
+        for line in block.lines() {
+            let (k, v) = match line.split_once(" = ") {
+                Some(kv) => kv,
+                None => continue,
+            };
+            match k {
+                "Path" => path = Some(PathBuf::from(v)),
+                "Size" => bytes = v.parse()?,
+                // fractional seconds are optional
+                "Modified" => {
+                    let secs = v.get(..19).unwrap_or(v);
+                    modified = NaiveDateTime::parse_from_str(secs, "%Y-%m-%d %H:%M:%S").ok();
+                }
+                "Folder" => is_dir = v == "+",
+                "Attributes" => is_dir = is_dir || v.starts_with('D'),
+                _ => {}
+            }
+        }
+
+        if let Some(path) = path {
+            entries.push(Entry {
+                path,
+                bytes,
+                modified,
+                is_dir,
+            });
+        }
+    }
+
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_redact() {
+        let cmd = ShellCmd {
+            program: "7z".into(),
+            args: vec!["t".into(), "a.7z".into(), pass("sw0rdf1sh")],
+            res: None,
+        };
+        let error = redact(ShellError::NonZero { cmd }).to_string();
+        assert!(error.contains("-p***"));
+        assert!(!error.contains("sw0rdf1sh"));
+    }
+
+    #[test]
+    fn test_parse_slt() -> Result<()> {
+        let txt = "7-Zip [64] 16.02
+
+Listing archive: a.7z
+
+--
+Path = a.7z
+Type = 7z
+Physical Size = 1234
+
+----------
+Path = Books/Music/bass.pdf
+Size = 90112
+Packed Size = 1024
+Modified = 2014-02-01 12:00:00.1234567
+Attributes = A -rw-r--r--
+CRC = 6B3D1E9A
+Encrypted = +
+
+Path = Books/Music
+Size = 0
+Packed Size = 0
+Modified = 2014-02-01 12:00:00
+Attributes = D drwxr-xr-x
+Encrypted = -
+";
+
+        let entries = parse_slt(txt)?;
+        assert_eq!(
+            entries,
+            vec![
+                Entry {
+                    path: PathBuf::from("Books/Music/bass.pdf"),
+                    bytes: 90112,
+                    modified: Some(NaiveDateTime::parse_from_str(
+                        "2014-02-01 12:00:00",
+                        "%Y-%m-%d %H:%M:%S"
+                    )?),
+                    is_dir: false,
+                },
+                Entry {
+                    path: PathBuf::from("Books/Music"),
+                    bytes: 0,
+                    modified: Some(NaiveDateTime::parse_from_str(
+                        "2014-02-01 12:00:00",
+                        "%Y-%m-%d %H:%M:%S"
+                    )?),
+                    is_dir: true,
+                },
+            ]
+        );
+
+        assert!(parse_slt("Listing archive: a.7z").is_err());
+
+        Ok(())
+    }
+}
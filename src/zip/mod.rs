@@ -0,0 +1,119 @@
+mod command;
+#[cfg(feature = "native-7z")]
+mod native;
+mod volume;
+
+use crate::config;
+use crate::crypto::{self, Scheme};
+use crate::fs::{self, IPathBuf};
+use crate::log;
+use crate::plan::{self, Action};
+use crate::shell;
+use anyhow::Result;
+use chrono::NaiveDateTime;
+use std::path::{Path, PathBuf};
+
+pub use volume::{first_volume_archive, join, split, split_for, split_to, volume_of, volume_path, volumes};
+
+/// A file or folder inside an archive
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub bytes: u64,
+    pub modified: Option<NaiveDateTime>,
+    pub is_dir: bool,
+}
+
+/// A way of reading and writing AES-256 encrypted 7z archives, given the
+/// derived password
+trait Backend {
+    /// Archives the contents of a folder, or a single file
+    fn create(&self, password: &str, from: &Path, to: &Path) -> Result<()>;
+    fn test(&self, password: &str, from: &Path) -> Result<()>;
+    /// Existing files are kept
+    fn extract(&self, password: &str, from: &Path, to_folder: &Path) -> Result<()>;
+    fn list(&self, password: &str, from: &Path) -> Result<Vec<Entry>>;
+}
+
+fn backend() -> Result<&'static dyn Backend> {
+    match config::get().yaml.zip.backend.as_str() {
+        "7z" => Ok(&command::Command),
+        #[cfg(feature = "native-7z")]
+        "native" => Ok(&native::Native),
+        other => Err(anyhow!("unsupported zip backend {}", other)),
+    }
+}
+
+pub fn gen_password(scheme: Scheme, password: &str, to: &Path) -> Result<String> {
+    let filename = not_zipped_filename(to);
+    crypto::gen_password(scheme, password, &filename)
+}
+
+pub fn create(scheme: Scheme, password: &str, from: &Path, to: &Path) -> Result<()> {
+    if plan::skip(Action::Zip {
+        from: from.into(),
+        to: to.into(),
+    }) {
+        return Ok(());
+    }
+
+    if to.exists() {
+        fs::remove_file(to)?;
+    }
+
+    let from_file = from.metadata().map(|m| m.is_file()).unwrap_or(false);
+
+    let full_password = gen_password(scheme, password, to)?;
+
+    log::info(&format!("zip {}", from.to_string()));
+
+    fs::create_parent_all(to)?;
+
+    backend()?.create(&full_password, from, to)?;
+
+    if from_file {
+        shell::out("touch", &["-r", &from.to_string(), &to.to_string()])?;
+    }
+
+    Ok(())
+}
+
+pub fn test(scheme: Scheme, password: &str, from: &Path) -> Result<()> {
+    let full_password = gen_password(scheme, password, from)?;
+    backend()?.test(&full_password, from)
+}
+
+pub fn detect_scheme(password: &str, from: &Path) -> Result<Scheme> {
+    for scheme in Scheme::ALL {
+        if test(scheme, password, from).is_ok() {
+            return Ok(scheme);
+        }
+    }
+
+    Err(anyhow!("no password scheme opens {}", from.to_string()))
+}
+
+pub fn extract(password: &str, from: &Path, to_folder: &Path) -> Result<()> {
+    let scheme = detect_scheme(password, from)?;
+    extract_with(scheme, password, from, to_folder)
+}
+
+pub fn extract_with(scheme: Scheme, password: &str, from: &Path, to_folder: &Path) -> Result<()> {
+    let full_password = gen_password(scheme, password, from)?;
+    backend()?.extract(&full_password, from, to_folder)
+}
+
+pub fn list(scheme: Scheme, password: &str, from: &Path) -> Result<Vec<Entry>> {
+    let full_password = gen_password(scheme, password, from)?;
+    backend()?.list(&full_password, from)
+}
+
+pub fn zipped_name(p: &Path) -> PathBuf {
+    let s = p.to_str().expect("invalid path");
+    format!("{}.7z", s).into()
+}
+
+fn not_zipped_filename(p: &Path) -> String {
+    let filename = p.file_name().expect("invalid filename").to_string_lossy();
+    filename.as_ref().trim_end_matches(".7z").to_owned()
+}
@@ -0,0 +1,189 @@
+use super::{Backend, Entry};
+use crate::fs::IPathBuf;
+use crate::smalldate;
+use anyhow::{Context, Result};
+use sevenz_rust::{
+    AesEncoderOptions, Password, SevenZArchiveEntry, SevenZMethod, SevenZReader, SevenZWriter,
+};
+use std::fs::File;
+use std::io;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// AES-256 + LZMA2 7z archives written and read in-process, streaming file
+/// contents, so passwords never reach a process argument
+pub(super) struct Native;
+
+fn push(writer: &mut SevenZWriter<File>, path: &Path, name: String) -> Result<()> {
+    let entry = SevenZArchiveEntry::from_path(path, name);
+    if path.is_dir() {
+        writer.push_archive_entry::<File>(entry, None)?;
+    } else {
+        writer.push_archive_entry(entry, Some(File::open(path)?))?;
+    }
+    Ok(())
+}
+
+/// Entry names use forward slashes, like the ones `7z` writes
+fn entry_name(relative: &Path) -> String {
+    relative
+        .iter()
+        .map(|c| c.to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
+
+impl Backend for Native {
+    fn create(&self, password: &str, from: &Path, to: &Path) -> Result<()> {
+        let mut writer = SevenZWriter::create(to)?;
+        writer.set_content_methods(vec![
+            AesEncoderOptions::new(Password::from(password)).into(),
+            SevenZMethod::LZMA2.into(),
+        ]);
+        // `7z a -p` leaves file names readable too
+        writer.set_encrypt_header(false);
+
+        if from.is_file() {
+            let filename = from.file_name().context("no filename")?;
+            push(&mut writer, from, filename.to_string_lossy().into_owned())?;
+        } else {
+            for entry in WalkDir::new(from).min_depth(1).sort_by_file_name() {
+                let entry = entry?;
+                let relative = entry.path().strip_prefix(from)?;
+                push(&mut writer, entry.path(), entry_name(relative))?;
+            }
+        }
+
+        writer.finish()?;
+        Ok(())
+    }
+
+    fn test(&self, password: &str, from: &Path) -> Result<()> {
+        let mut reader = SevenZReader::open(from, Password::from(password))?;
+        reader.for_each_entries(|_, data| {
+            io::copy(data, &mut io::sink())?;
+            Ok(true)
+        })?;
+        Ok(())
+    }
+
+    fn extract(&self, password: &str, from: &Path, to_folder: &Path) -> Result<()> {
+        sevenz_rust::decompress_with_extract_fn_and_password(
+            File::open(from)?,
+            to_folder,
+            Password::from(password),
+            |entry, data, dest| {
+                if !entry.is_directory() && dest.exists() {
+                    // solid blocks have to be read through anyway
+                    io::copy(data, &mut io::sink())?;
+                    return Ok(true);
+                }
+                sevenz_rust::default_entry_extract_fn(entry, data, dest)
+            },
+        )
+        .with_context(|| format!("Failed to extract {}", from.to_string()))
+    }
+
+    fn list(&self, password: &str, from: &Path) -> Result<Vec<Entry>> {
+        let reader = SevenZReader::open(from, Password::from(password))?;
+
+        let entries = reader
+            .archive()
+            .files
+            .iter()
+            .map(|f| Entry {
+                path: PathBuf::from(&f.name),
+                bytes: f.size,
+                modified: if f.has_last_modified_date {
+                    smalldate::naive_from_timestamp(f.last_modified_date.to_unix_time())
+                } else {
+                    None
+                },
+                is_dir: f.is_directory,
+            })
+            .collect();
+
+        Ok(entries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::command::Command;
+    use super::*;
+    use crate::fs::IPathBuf;
+
+    #[test]
+    fn test_roundtrip() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let from = dir.path().join("from");
+        std::fs::create_dir_all(from.join("books/fiction"))?;
+        std::fs::write(from.join("books/fiction/alien.txt"), "alien")?;
+        std::fs::write(from.join("monster.txt"), vec![7u8; 100_000])?;
+
+        let zip = dir.path().join("a.7z");
+        Native.create("sw0rdf1sh", &from, &zip)?;
+        assert!(Native.test("sw0rdf1sh", &zip).is_ok());
+        assert!(Native.test("wrong", &zip).is_err());
+
+        let mut files = Native
+            .list("sw0rdf1sh", &zip)?
+            .into_iter()
+            .filter(|e| !e.is_dir)
+            .map(|e| (e.path.to_string(), e.bytes))
+            .collect::<Vec<_>>();
+        files.sort();
+        assert_eq!(
+            files,
+            vec![
+                ("books/fiction/alien.txt".into(), 5),
+                ("monster.txt".into(), 100_000)
+            ]
+        );
+
+        let to = dir.path().join("to");
+        std::fs::create_dir_all(&to)?;
+        std::fs::write(to.join("monster.txt"), "kept")?;
+        Native.extract("sw0rdf1sh", &zip, &to)?;
+        assert_eq!(
+            std::fs::read_to_string(to.join("books/fiction/alien.txt"))?,
+            "alien"
+        );
+        assert_eq!(std::fs::read_to_string(to.join("monster.txt"))?, "kept");
+
+        Ok(())
+    }
+
+    /// Archives written by either backend open with the other one
+    #[test]
+    fn test_interop() -> Result<()> {
+        if which::which("7z").is_err() {
+            eprintln!("7z not installed, skipping");
+            return Ok(());
+        }
+        let _ = crate::shell::setup();
+
+        let dir = tempfile::tempdir()?;
+        let from = dir.path().join("from");
+        std::fs::create_dir_all(from.join("books/fiction"))?;
+        std::fs::write(from.join("books/fiction/alien.txt"), "alien")?;
+        std::fs::write(from.join("monster.txt"), vec![7u8; 100_000])?;
+
+        let backends: [(&dyn Backend, &dyn Backend); 2] = [(&Native, &Command), (&Command, &Native)];
+        for (i, (writer, reader)) in backends.iter().enumerate() {
+            let zip = dir.path().join(format!("{}.7z", i));
+            writer.create("sw0rdf1sh", &from, &zip)?;
+            assert!(reader.test("wrong", &zip).is_err());
+
+            let to = dir.path().join(format!("to{}", i));
+            reader.extract("sw0rdf1sh", &zip, &to)?;
+            assert_eq!(
+                std::fs::read_to_string(to.join("books/fiction/alien.txt"))?,
+                "alien"
+            );
+            assert_eq!(std::fs::read(to.join("monster.txt"))?, vec![7u8; 100_000]);
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,119 @@
+use crate::fs::{self, IPathBuf};
+use crate::plan;
+use crate::provider::{self, ProviderId};
+use anyhow::Result;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// `a.7z` -> `a.7z.001`, numbered from 1
+pub fn volume_path(archive: &Path, i: usize) -> PathBuf {
+    PathBuf::from(format!("{}.{:03}", archive.to_string(), i))
+}
+
+/// `a.7z.003` -> (`a.7z`, 3), or None if the path isn't a volume of an archive
+pub fn volume_of(path: &Path) -> Option<(PathBuf, usize)> {
+    let (archive, i) = path.to_str()?.rsplit_once('.')?;
+    if !archive.ends_with(".7z") || i.len() != 3 {
+        return None;
+    }
+    let i = i.parse().ok().filter(|i| *i > 0)?;
+    Some((PathBuf::from(archive), i))
+}
+
+/// `a.7z.001` -> `a.7z`, or None if the path isn't the first volume of an archive
+pub fn first_volume_archive(path: &Path) -> Option<PathBuf> {
+    volume_of(path)
+        .filter(|(_, i)| *i == 1)
+        .map(|(archive, _)| archive)
+}
+
+/// Consecutive volumes of the archive that exist on disk
+pub fn volumes(archive: &Path) -> Vec<PathBuf> {
+    (1..)
+        .map(|i| volume_path(archive, i))
+        .take_while(|v| v.exists())
+        .collect()
+}
+
+/// Splits the archive into volumes of at most `volume_kb`, the same way
+/// `7z -v` does, and removes it
+pub fn split(archive: &Path, volume_kb: u32) -> Result<Vec<PathBuf>> {
+    let volumes = split_to(archive, volume_kb, archive)?;
+    fs::remove_file(archive)?;
+    Ok(volumes)
+}
+
+/// Writes the volumes of `from` as volumes of `to`, keeping `from`
+pub fn split_to(from: &Path, volume_kb: u32, to: &Path) -> Result<Vec<PathBuf>> {
+    let volume_bytes = volume_kb.max(1) as u64 * 1024;
+    let len = std::fs::metadata(from)?.len();
+    let n = (len / volume_bytes + u64::from(len % volume_bytes != 0)).max(1) as usize;
+
+    let mut reader = BufReader::new(fs::open(from)?);
+    let mut volumes = vec![];
+    for i in 1..=n {
+        let volume = volume_path(to, i);
+        let mut writer = File::create(&volume)?;
+        io::copy(&mut (&mut reader).take(volume_bytes), &mut writer)?;
+        volumes.push(volume);
+    }
+
+    Ok(volumes)
+}
+
+/// Splits the archive only if it is bigger than the provider's `max_object_kb`
+pub fn split_for(provider_id: &ProviderId, archive: &Path) -> Result<Vec<PathBuf>> {
+    let max_object_kb = provider::get(provider_id)?.max_object_kb;
+    match max_object_kb {
+        Some(kb) if !plan::is_dry_run() && std::fs::metadata(archive)?.len() > kb as u64 * 1024 => {
+            split(archive, kb)
+        }
+        _ => Ok(vec![archive.into()]),
+    }
+}
+
+/// Concatenates volumes back into a single archive
+pub fn join(volumes: &[PathBuf], to: &Path) -> Result<()> {
+    if volumes.is_empty() {
+        return Err(anyhow!("no volumes for {}", to.to_string()));
+    }
+
+    fs::create_parent_all(to)?;
+    let mut writer = File::create(to)?;
+    for volume in volumes {
+        io::copy(&mut fs::open(volume)?, &mut writer)?;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_split_and_join() -> Result<()> {
+        let dir = tempfile::tempdir()?;
+        let archive = dir.path().join("a.7z");
+        let contents = (0..5000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        std::fs::write(&archive, &contents)?;
+
+        let parts = split(&archive, 2)?;
+        assert_eq!(parts.len(), 3);
+        assert!(!archive.exists());
+        assert_eq!(std::fs::metadata(&parts[0])?.len(), 2048);
+        assert_eq!(std::fs::metadata(&parts[2])?.len(), 5000 - 4096);
+        assert_eq!(volumes(&archive), parts);
+        assert_eq!(first_volume_archive(&parts[0]), Some(archive.clone()));
+        assert_eq!(first_volume_archive(&parts[1]), None);
+        assert_eq!(volume_of(&parts[2]), Some((archive.clone(), 3)));
+        assert_eq!(volume_of(Path::new("a.zip.001")), None);
+        assert_eq!(volume_of(Path::new("a.7z.1")), None);
+
+        join(&parts, &archive)?;
+        assert_eq!(std::fs::read(&archive)?, contents);
+
+        Ok(())
+    }
+}